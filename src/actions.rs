@@ -1,94 +1,177 @@
-use std::{borrow::Borrow, fmt::Debug, io::IsTerminal, process::exit, sync::Arc};
+use std::{
+    collections::HashMap,
+    pin::pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use reqwest::Url;
-use reqwest_middleware::ClientWithMiddleware;
+use futures::TryStreamExt;
 use rusqlite::Connection;
-use serde::de::DeserializeOwned;
 use tokio::sync::Mutex;
 
 use crate::{
-    cli::ActionCommands,
+    client::{BazarrClient, ListQuery, Page},
     data_types::{
-        request::ActionPayload,
-        response::{Episode, Movie, PaginatedResponse, TVShow},
+        request::{ActionCommands, ActionPayload, MediaType},
+        response::{Episode, Movie, Subtitle, TVShow},
     },
     db::{
-        filter_unprocessed_episodes, filter_unprocessed_movies, mark_episode_subtitle_processed,
-        mark_movie_subtitle_processed,
+        filter_unprocessed_episodes, filter_unprocessed_movies, log_action,
+        mark_episode_subtitle_processed, mark_movie_subtitle_processed, now, save_checkpoint,
+        ActionLogEntry, ActionResult, ProcessedKeys, RunCheckpoint,
     },
+    error::Error,
+    progress::{Event, Reporter, Silent},
+    rate_limit::AdaptiveThrottle,
+    shutdown::Shutdown,
 };
 
+/// Number of subtitles per outcome in the current run
+#[derive(Debug, Default)]
+pub struct RunStats {
+    pub succeeded: AtomicU64,
+    pub failed: AtomicU64,
+    pub skipped: AtomicU64,
+    pub interrupted: AtomicU64,
+}
+
+impl RunStats {
+    /// Add the counts of another run, e.g. on another Bazarr instance
+    pub fn add(&self, other: &RunStats) {
+        for (total, count) in [
+            (&self.succeeded, &other.succeeded),
+            (&self.failed, &other.failed),
+            (&self.skipped, &other.skipped),
+            (&self.interrupted, &other.interrupted),
+        ] {
+            total.fetch_add(count.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    /// Human-readable counts, including interrupted subtitles for partial runs
+    pub fn summary(&self, partial: bool) -> String {
+        let summary = format!(
+            "{} succeeded, {} failed, {} skipped",
+            self.succeeded.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+        );
+        if partial {
+            format!(
+                "{summary}, {} interrupted",
+                self.interrupted.load(Ordering::Relaxed)
+            )
+        } else {
+            summary
+        }
+    }
+
+    fn record(&self, result: ActionResult) {
+        let counter = match result {
+            ActionResult::Success => &self.succeeded,
+            ActionResult::Failed | ActionResult::ConnectionError => &self.failed,
+            ActionResult::Interrupted => &self.interrupted,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Performs an action on every subtitle of the library, recording progress in the database
 pub struct Action {
-    pub client: ClientWithMiddleware,
-    pub base_url: Url,
+    pub client: BazarrClient,
+    pub instance: String,
+    pub run_id: String,
     pub action: ActionCommands,
     pub ids: Vec<u32>,
     pub offset: u32,
     pub limit: Option<u32>,
     pub skip_processed: bool,
     pub language_code: Option<String>,
-    pub pb: ProgressBar,
     pub db_conn: Arc<Mutex<Connection>>,
-    pub is_tty: bool,
+    /// Receives the progress of the run, which is not shown by default
+    pub reporter: Arc<dyn Reporter>,
+    /// Checkpoint of the interrupted run being resumed
+    pub resume: Option<RunCheckpoint>,
+    pub shutdown: Shutdown,
+    /// Time to wait for the current subtitle once a shutdown has been requested
+    pub shutdown_timeout: Duration,
+    pub stats: RunStats,
+    /// Adjusts the request rate to the latency of actions
+    pub throttle: Option<Arc<AdaptiveThrottle>>,
+    /// Number of TV shows whose episodes are requested at once
+    pub series_per_request: usize,
 }
 
-impl Action {
-    pub fn new(
-        client: ClientWithMiddleware,
-        base_url: Url,
-        db_conn: Arc<Mutex<Connection>>,
-    ) -> Self {
-        let is_tty = std::io::stdout().is_terminal();
-        let pb = if is_tty {
-            ProgressBar::new(0)
-        } else {
-            // For non-TTY environments, hide the progress bar
-            ProgressBar::hidden()
+/// Drop the records preceding the record with the given ID and collect the keys of its
+/// subtitles up to and including the given language, as they have already been completed.
+/// Returns None if the record is not found.
+fn skip_completed<T>(
+    records: &mut Vec<T>,
+    media_id: u32,
+    language_code: Option<&str>,
+    id_of: impl Fn(&T) -> u32,
+    subtitles_of: impl Fn(&T) -> &[Subtitle],
+) -> Option<ProcessedKeys> {
+    let idx = records
+        .iter()
+        .position(|record| id_of(record) == media_id)?;
+    records.drain(..idx);
+
+    let mut completed = ProcessedKeys::new();
+    for subtitle in subtitles_of(&records[0]) {
+        let Some(code) = &subtitle.audio_language_item.code2 else {
+            continue;
         };
+        completed.insert((media_id, code.clone()));
+        if Some(code.as_str()) == language_code {
+            break;
+        }
+    }
+    Some(completed)
+}
+
+/// Group the episodes of a batch of TV shows by TV show, keeping their order
+fn group_by_series(episodes: Vec<Episode>) -> HashMap<u32, Vec<Episode>> {
+    let mut episodes_by_series: HashMap<u32, Vec<Episode>> = HashMap::new();
+    for episode in episodes {
+        episodes_by_series
+            .entry(episode.sonarr_series_id)
+            .or_default()
+            .push(episode);
+    }
+    episodes_by_series
+}
+
+impl Action {
+    pub fn new(client: BazarrClient, instance: String, db_conn: Arc<Mutex<Connection>>) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let run_id = format!("{}-{}", now.as_millis(), std::process::id());
         Self {
             client,
-            base_url,
+            instance,
+            run_id,
             action: ActionCommands::OCRFixes,
             ids: Vec::new(),
             offset: 0,
             skip_processed: false,
             language_code: None,
             limit: None,
-            pb,
             db_conn,
-            is_tty,
-        }
-    }
-
-    /// set message on progress bar or print to stdout based on TTY
-    fn log_info(&self, pb: &ProgressBar, msg: impl Into<String>) {
-        let message = msg.into();
-        if self.is_tty {
-            pb.set_message(message);
-        } else {
-            println!("{}", message);
+            reporter: Arc::new(Silent),
+            resume: None,
+            shutdown: Shutdown::default(),
+            shutdown_timeout: Duration::from_secs(30),
+            stats: RunStats::default(),
+            throttle: None,
+            series_per_request: 10,
         }
     }
 
-    /// set error message on progress bar or print to stderr based on TTY
-    fn log_error(&self, pb: &ProgressBar, msg: impl Into<String>) {
-        let message = msg.into();
-        if self.is_tty {
-            pb.set_message(message);
-        } else {
-            eprintln!("{}", message);
-        }
-    }
-
-    /// finish progress bar with message or print to stdout based on TTY
-    fn finish(&self, pb: &ProgressBar, msg: impl Into<String>) {
-        let message = msg.into();
-        if self.is_tty {
-            pb.finish_with_message(message);
-        } else {
-            println!("{}", message);
-        }
+    fn report(&self, event: Event) {
+        self.reporter.report(event);
     }
 
     /// Check if subtitle matches the language filter (if specified)
@@ -100,333 +183,597 @@ impl Action {
         }
     }
 
-    async fn get_all<T>(&self, url: Url) -> Result<PaginatedResponse<T>, Box<dyn std::error::Error>>
-    where
-        T: DeserializeOwned,
-        T: Debug,
-    {
-        let req = self.client.get(url);
-        let res = req.send().await?;
-        let body: PaginatedResponse<T> = res.json().await?;
-        Ok(body)
+    /// Records selected by the ID, offset and limit filters
+    fn list_query(&self) -> ListQuery {
+        ListQuery {
+            ids: self.ids.clone(),
+            start: self.offset,
+            length: self.limit,
+        }
+    }
+
+    /// Check if the subtitle has already been processed (only when skipping processed subtitles)
+    fn is_processed(&self, processed: &ProcessedKeys, media_id: u32, subtitle: &Subtitle) -> bool {
+        match &subtitle.audio_language_item.code2 {
+            Some(code) => processed.contains(&(media_id, code.clone())),
+            None => false,
+        }
+    }
+
+    /// Report the end of the run with the number of subtitles per outcome
+    fn report_finished(&self) {
+        self.report(Event::Finished {
+            stats: &self.stats,
+            interrupted: self.shutdown.is_requested(),
+        });
     }
 
-    async fn limit_records(&self, mut url: Url, query_param: &str) -> Url {
-        if !self.ids.is_empty() {
-            for id in &self.ids {
-                url.query_pairs_mut()
-                    .append_pair(query_param, &id.to_string());
+    /// Count the outcome of an attempted action and append it to the action log
+    async fn record(&self, entry: ActionLogEntry) -> Result<(), Error> {
+        self.stats.record(entry.result);
+        if let Some(throttle) = &self.throttle {
+            let failed = matches!(
+                entry.result,
+                ActionResult::Failed | ActionResult::ConnectionError
+            );
+            if entry.result != ActionResult::Interrupted && throttle.observe(failed).await.is_some()
+            {
+                self.report(Event::RateChanged {
+                    rate: throttle.rate(),
+                    latency: throttle.latency(),
+                });
             }
-        } else if self.limit.is_some() || self.offset > 0 {
-            let length = match self.limit {
-                Some(val) => val,
-                None => u32::MAX,
-            };
-            url.query_pairs_mut()
-                .append_pair("length", &length.to_string())
-                .append_pair("start", &self.offset.to_string());
         }
-        url
+        log_action(self.db_conn.clone(), entry).await?;
+        Ok(())
+    }
+
+    /// Record the last completed subtitle so that the run can be resumed
+    async fn checkpoint(
+        &self,
+        series_id: Option<u32>,
+        media_id: u32,
+        language_code: Option<String>,
+    ) -> Result<(), Error> {
+        save_checkpoint(
+            self.db_conn.clone(),
+            self.run_id.clone(),
+            series_id,
+            media_id,
+            language_code,
+        )
+        .await?;
+        Ok(())
     }
 
-    async fn perform(
+    /// Create an action log entry for a subtitle, to be completed once the action finishes
+    fn log_entry(
         &self,
-        mut payload: ActionPayload,
-    ) -> Result<reqwest::Response, reqwest_middleware::Error> {
-        let mut url = self.base_url.clone();
-        url.path_segments_mut().unwrap().push("subtitles");
-        let action_string: String = self.action.to_string();
-        url.query_pairs_mut().append_pair("action", &action_string);
-        if let ActionCommands::Sync(sync_options) = &self.action.borrow() {
-            payload.reference = sync_options.reference.clone();
-            payload.max_offset_seconds = sync_options.max_offset_seconds;
-            payload.no_fix_framerate = Some(sync_options.no_fix_framerate);
-            payload.gss = Some(sync_options.gss);
+        media_type: MediaType,
+        media_id: u32,
+        title: &str,
+        subtitle: &Subtitle,
+    ) -> ActionLogEntry {
+        ActionLogEntry {
+            run_id: self.run_id.clone(),
+            instance: self.instance.clone(),
+            action: self.action.to_string(),
+            options: serde_json::to_string(&self.action).ok(),
+            media_type,
+            media_id,
+            title: title.to_string(),
+            language_code: subtitle.audio_language_item.code2.clone(),
+            subtitle_path: subtitle.path.clone(),
+            result: ActionResult::Failed,
+            http_status: None,
+            error: None,
+            duration_ms: 0,
+            performed_at: now(),
         }
-        let body = serde_json::to_vec(&payload).unwrap();
-        self.client
-            .patch(url)
-            .header("Content-Type", "application/json")
-            .body(body)
-            .send()
-            .await
     }
 
-    async fn process_episode_subtitle(&self, pb: &ProgressBar, episode: Episode) {
+    async fn process_episode_subtitle(
+        &self,
+        episode: Episode,
+        processed: &ProcessedKeys,
+    ) -> Result<(), Error> {
         for subtitle in episode.subtitles {
-            if !subtitle.is_valid() {
-                continue;
+            if self.shutdown.is_requested() {
+                return Ok(());
             }
 
+            let Some(payload) = ActionPayload::new(episode.sonarr_episode_id, "episode", &subtitle)
+            else {
+                continue;
+            };
+
             if !self.matches_language_filter(subtitle.audio_language_item.code2.as_ref()) {
                 continue;
             }
 
-            let msg = format!(
-                "Performing action {} on {} subtitle of episode {}",
-                self.action.to_string(),
-                subtitle.audio_language_item.name,
-                episode.title,
+            if self.is_processed(processed, episode.sonarr_episode_id, &subtitle) {
+                self.report(Event::SubtitleSkipped {
+                    title: &episode.title,
+                    subtitle: &subtitle,
+                });
+                self.stats.skipped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            self.report(Event::Performing {
+                title: &episode.title,
+                subtitle: &subtitle,
+            });
+
+            let mut entry = self.log_entry(
+                MediaType::TVShow,
+                episode.sonarr_episode_id,
+                &episode.title,
+                &subtitle,
             );
-            self.log_info(pb, msg);
-
-            let payload = ActionPayload::new(episode.sonarr_episode_id, "episode", &subtitle);
-            match self.perform(payload).await {
-                Ok(res) => match res.error_for_status() {
-                    Ok(_) => {
-                        let msg = format!(
-                            "Successfully performed action {} on {} subtitle of episode {}",
-                            self.action.to_string(),
-                            subtitle.audio_language_item.name,
-                            episode.title,
-                        );
-                        self.log_info(pb, msg);
-                        let _ = mark_episode_subtitle_processed(
-                            self.db_conn.clone(),
-                            episode.sonarr_episode_id,
-                            episode.title.clone(),
-                            subtitle,
-                        )
-                        .await;
+            let started = Instant::now();
+            let response = tokio::select! {
+                response = self.client.perform(&self.action, payload) => response,
+                _ = self.shutdown.grace_period_elapsed(self.shutdown_timeout) => {
+                    entry.duration_ms = started.elapsed().as_millis() as u64;
+                    entry.result = ActionResult::Interrupted;
+                    entry.error = Some("Stopped before Bazarr responded".to_string());
+                    self.record(entry).await?;
+                    if self.shutdown.is_aborted() {
+                        return Err(Error::Interrupted);
                     }
-                    Err(err) => {
-                        let msg = format!(
-                            "Error performing action {} on {} subtitle of episode {}: {}",
-                            self.action.to_string(),
-                            subtitle.audio_language_item.name,
-                            episode.title,
-                            err,
-                        );
-                        self.log_error(pb, msg);
-                    }
-                },
+                    return Ok(());
+                }
+            };
+            entry.duration_ms = started.elapsed().as_millis() as u64;
+            self.report(Event::Performed {
+                title: &episode.title,
+                subtitle: &subtitle,
+                result: response.as_ref().map(|_| ()),
+            });
+            match response {
+                Ok(status) => {
+                    entry.http_status = Some(status.as_u16());
+                    entry.result = ActionResult::Success;
+                    let _ = mark_episode_subtitle_processed(
+                        self.db_conn.clone(),
+                        self.instance.clone(),
+                        episode.sonarr_episode_id,
+                        episode.title.clone(),
+                        subtitle,
+                        self.action.to_string(),
+                    )
+                    .await;
+                }
+                Err(err) if err.is_response_timeout() => {
+                    // Bazarr may still be working on it, only this subtitle failed
+                    entry.error = Some(err.to_string());
+                }
+                Err(Error::Connection(err)) => {
+                    entry.result = ActionResult::ConnectionError;
+                    entry.error = Some(err.to_string());
+                    self.record(entry).await?;
+                    return Err(Error::Connection(err));
+                }
                 Err(err) => {
-                    let msg = format!("Error connecting to Bazarr: {}", err);
-                    self.log_error(&self.pb, msg);
-                    exit(1);
+                    entry.http_status = err.http_status().map(|status| status.as_u16());
+                    entry.error = Some(err.to_string());
                 }
             }
+            let language_code = entry.language_code.clone();
+            self.record(entry).await?;
+            self.checkpoint(
+                Some(episode.sonarr_series_id),
+                episode.sonarr_episode_id,
+                language_code,
+            )
+            .await?;
         }
+        Ok(())
     }
 
-    async fn process_movie_subtitle(&self, movie: Movie) {
+    async fn process_movie_subtitle(
+        &self,
+        movie: Movie,
+        processed: &ProcessedKeys,
+    ) -> Result<(), Error> {
         for subtitle in movie.subtitles {
-            if !subtitle.is_valid() {
-                continue;
+            if self.shutdown.is_requested() {
+                return Ok(());
             }
 
+            let Some(payload) = ActionPayload::new(movie.radarr_id, "movie", &subtitle) else {
+                continue;
+            };
+
             if !self.matches_language_filter(subtitle.audio_language_item.code2.as_ref()) {
                 continue;
             }
 
-            let msg = format!(
-                "Performing action {} on {} subtitle of movie {}",
-                self.action.to_string(),
-                subtitle.audio_language_item.name,
-                movie.title,
-            );
-            self.log_info(&self.pb, msg);
-
-            let payload = ActionPayload::new(movie.radarr_id, "movie", &subtitle);
-            match self.perform(payload).await {
-                Ok(res) => match res.error_for_status() {
-                    Ok(_) => {
-                        let msg = format!(
-                            "Successfully performed action {} on {} subtitle of movie {}",
-                            self.action.to_string(),
-                            subtitle.audio_language_item.name,
-                            movie.title,
-                        );
-                        self.log_info(&self.pb, msg);
-                        let _ = mark_movie_subtitle_processed(
-                            self.db_conn.clone(),
-                            movie.radarr_id,
-                            movie.title.clone(),
-                            subtitle,
-                        )
-                        .await;
-                    }
-                    Err(err) => {
-                        let msg = format!(
-                            "Error performing action {} on {} subtitle of episode {}: {}",
-                            self.action.to_string(),
-                            subtitle.audio_language_item.name,
-                            movie.title,
-                            err,
-                        );
-                        self.log_error(&self.pb, msg);
+            if self.is_processed(processed, movie.radarr_id, &subtitle) {
+                self.report(Event::SubtitleSkipped {
+                    title: &movie.title,
+                    subtitle: &subtitle,
+                });
+                self.stats.skipped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            self.report(Event::Performing {
+                title: &movie.title,
+                subtitle: &subtitle,
+            });
+
+            let mut entry =
+                self.log_entry(MediaType::Movie, movie.radarr_id, &movie.title, &subtitle);
+            let started = Instant::now();
+            let response = tokio::select! {
+                response = self.client.perform(&self.action, payload) => response,
+                _ = self.shutdown.grace_period_elapsed(self.shutdown_timeout) => {
+                    entry.duration_ms = started.elapsed().as_millis() as u64;
+                    entry.result = ActionResult::Interrupted;
+                    entry.error = Some("Stopped before Bazarr responded".to_string());
+                    self.record(entry).await?;
+                    if self.shutdown.is_aborted() {
+                        return Err(Error::Interrupted);
                     }
-                },
+                    return Ok(());
+                }
+            };
+            entry.duration_ms = started.elapsed().as_millis() as u64;
+            self.report(Event::Performed {
+                title: &movie.title,
+                subtitle: &subtitle,
+                result: response.as_ref().map(|_| ()),
+            });
+            match response {
+                Ok(status) => {
+                    entry.http_status = Some(status.as_u16());
+                    entry.result = ActionResult::Success;
+                    let _ = mark_movie_subtitle_processed(
+                        self.db_conn.clone(),
+                        self.instance.clone(),
+                        movie.radarr_id,
+                        movie.title.clone(),
+                        subtitle,
+                        self.action.to_string(),
+                    )
+                    .await;
+                }
+                Err(err) if err.is_response_timeout() => {
+                    // Bazarr may still be working on it, only this subtitle failed
+                    entry.error = Some(err.to_string());
+                }
+                Err(Error::Connection(err)) => {
+                    entry.result = ActionResult::ConnectionError;
+                    entry.error = Some(err.to_string());
+                    self.record(entry).await?;
+                    return Err(Error::Connection(err));
+                }
                 Err(err) => {
-                    let msg = format!("Error connecting to Bazarr: {}", err);
-                    self.log_error(&self.pb, msg);
-                    exit(1);
+                    entry.http_status = err.http_status().map(|status| status.as_u16());
+                    entry.error = Some(err.to_string());
                 }
             }
+            let language_code = entry.language_code.clone();
+            self.record(entry).await?;
+            self.checkpoint(None, movie.radarr_id, language_code)
+                .await?;
         }
+        Ok(())
     }
 
-    pub async fn movies(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.is_tty {
-            self.pb.set_style(
-                ProgressStyle::with_template(
-                    "[{bar:60.green/yellow}] {pos:>7}/{len:7} Movies\n{msg}",
-                )
-                .unwrap()
-                .progress_chars("##-"),
-            );
+    /// Report the number of selected items with the first page of a listing,
+    /// or the items of each page when Bazarr does not report the total
+    fn track_progress<T>(&self, query: &ListQuery, page: &Page<T>, first_page: bool) {
+        match page.total {
+            Some(total) if first_page => self.report(Event::Selected {
+                count: query.selected(total) as u64,
+            }),
+            Some(_) => {}
+            None => self.report(Event::Listed {
+                count: page.records.len() as u64,
+            }),
         }
+    }
 
-        let mut url = self.base_url.clone();
-        url.path_segments_mut().unwrap().push("movies");
-        url = self.limit_records(url, "radarrid[]").await;
-        let response = self.get_all::<Movie>(url).await?;
-        let mut movies = response.data;
-        if self.skip_processed {
-            let initial_len = movies.len();
-            movies = filter_unprocessed_movies(self.db_conn.clone(), movies).await?;
-            let after_len = movies.len();
-            let difference = initial_len - after_len;
-            println!("Skipped {difference} already processed movies...");
-        }
-        let num_movies: u64 = movies.len() as u64;
-        if num_movies == 0 {
-            self.finish(&self.pb, "No movies found");
-            return Ok(());
-        }
+    pub async fn movies(&self) -> Result<(), Error> {
+        self.report(Event::Started {
+            media_type: MediaType::Movie,
+            action: &self.action,
+        });
+
+        let query = self.list_query();
+        let mut resume = self.resume.as_ref().and_then(|c| c.media_id);
+        let language_code = self
+            .resume
+            .as_ref()
+            .and_then(|c| c.language_code.as_deref());
+        // movies are listed page by page, again from the start if the resumed movie is not found
+        loop {
+            let mut seeking = resume;
+            let mut first_page = true;
+            let mut listed = 0;
+            let mut pages = pin!(self.client.movie_pages(&query));
+            while let Some(page) = self.shutdown.until_aborted(pages.try_next()).await? {
+                self.track_progress(&query, &page, first_page);
+                first_page = false;
+                listed += page.records.len();
+
+                let mut movies = page.records;
+                let mut completed = ProcessedKeys::new();
+                if let Some(media_id) = seeking {
+                    let initial_len = movies.len();
+                    let Some(keys) = skip_completed(
+                        &mut movies,
+                        media_id,
+                        language_code,
+                        |m| m.radarr_id,
+                        |m| &m.subtitles,
+                    ) else {
+                        self.report(Event::Skipped {
+                            count: initial_len as u64,
+                        });
+                        continue;
+                    };
+                    self.report(Event::Resuming {
+                        title: &movies[0].title,
+                    });
+                    self.report(Event::Skipped {
+                        count: (initial_len - movies.len()) as u64,
+                    });
+                    completed = keys;
+                    seeking = None;
+                }
+                let mut processed = ProcessedKeys::new();
+                if self.skip_processed {
+                    let initial_len = movies.len();
+                    (movies, processed) = filter_unprocessed_movies(
+                        self.db_conn.clone(),
+                        self.instance.clone(),
+                        movies,
+                    )
+                    .await?;
+                    self.report(Event::AlreadyProcessed {
+                        count: (initial_len - movies.len()) as u64,
+                    });
+                }
+                processed.extend(completed);
 
-        if !self.is_tty {
-            println!("Processing {} movies...", num_movies);
-        }
+                for movie in movies {
+                    if self.shutdown.is_requested() {
+                        break;
+                    }
+                    let (id, title) = (movie.radarr_id, movie.title.clone());
+                    self.report(Event::ItemStarted { id, title: &title });
+                    self.process_movie_subtitle(movie, &processed).await?;
+                    self.report(Event::ItemFinished { id, title: &title });
+                }
+                if self.shutdown.is_requested() {
+                    break;
+                }
+            }
 
-        self.pb.set_length(num_movies);
-        for (idx, movie) in movies.into_iter().enumerate() {
-            if !self.is_tty {
-                println!("Processing movie {}/{}", idx + 1, num_movies);
+            if listed == 0 {
+                self.report(Event::NothingFound);
+                return Ok(());
+            }
+            if seeking.is_some() && !self.shutdown.is_requested() {
+                self.report(Event::ResumeNotFound);
+                resume = None;
+                continue;
             }
-            self.process_movie_subtitle(movie).await;
-            self.pb.inc(1);
+            break;
         }
 
-        self.finish(
-            &self.pb,
-            format!(
-                "Finished performing action {} on all movies",
-                self.action.to_string(),
-            ),
-        );
+        self.report_finished();
         Ok(())
     }
 
-    pub async fn tv_shows(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mp = MultiProgress::new();
-        let pb_main = mp.add(self.pb.clone());
-
-        if self.is_tty {
-            pb_main.set_style(
-                ProgressStyle::with_template(
-                    "[{bar:60.green/yellow}] {pos:>7}/{len:7} TV Shows\n{msg}",
-                )
-                .unwrap()
-                .progress_chars("##-"),
-            );
+    /// Perform the action on the episodes of a TV show
+    async fn process_series(
+        &self,
+        series: &TVShow,
+        mut episodes: Vec<Episode>,
+        resume: Option<RunCheckpoint>,
+    ) -> Result<(), Error> {
+        let (id, title) = (series.sonarr_series_id, series.title.as_str());
+        self.report(Event::ItemStarted { id, title });
+
+        let mut completed = ProcessedKeys::new();
+        if let Some(media_id) = resume.as_ref().and_then(|c| c.media_id) {
+            let language_code = resume.as_ref().and_then(|c| c.language_code.as_deref());
+            if let Some(keys) = skip_completed(
+                &mut episodes,
+                media_id,
+                language_code,
+                |e| e.sonarr_episode_id,
+                |e| &e.subtitles,
+            ) {
+                completed = keys;
+            }
         }
-
-        let mut url = self.base_url.clone();
-        url.path_segments_mut().unwrap().push("series");
-        url = self.limit_records(url, "seriesid[]").await;
-        let response = self.get_all::<TVShow>(url.clone()).await?;
-        let num_series: u64 = response.data.len() as u64;
-        if num_series == 0 {
-            self.finish(&pb_main, "No tv shows found");
-            return Ok(());
+        let mut processed = ProcessedKeys::new();
+        if self.skip_processed {
+            let initial_len = episodes.len();
+            (episodes, processed) =
+                filter_unprocessed_episodes(self.db_conn.clone(), self.instance.clone(), episodes)
+                    .await?;
+            self.report(Event::AlreadyProcessed {
+                count: (initial_len - episodes.len()) as u64,
+            });
         }
-
-        if !self.is_tty {
-            println!("Processing {} TV shows...", num_series);
+        processed.extend(completed);
+        self.report(Event::Episodes {
+            count: episodes.len() as u64,
+        });
+
+        for episode in episodes {
+            if self.shutdown.is_requested() {
+                break;
+            }
+            let (episode_id, episode_title) = (episode.sonarr_episode_id, episode.title.clone());
+            self.report(Event::EpisodeStarted {
+                id: episode_id,
+                title: &episode_title,
+            });
+            self.process_episode_subtitle(episode, &processed).await?;
+            self.report(Event::EpisodeFinished {
+                id: episode_id,
+                title: &episode_title,
+            });
         }
 
-        pb_main.set_length(num_series);
-        let sub_pb = if self.is_tty {
-            let pb = mp.insert_after(&pb_main, ProgressBar::new(0));
-            pb.set_style(
-                ProgressStyle::with_template(
-                    "[{bar:60.cyan/blue}] {pos:>7}/{len:7} Episodes\n{msg}",
-                )
-                .unwrap()
-                .progress_chars("##-"),
-            );
-            pb
-        } else {
-            ProgressBar::hidden()
-        };
+        self.report(Event::ItemFinished { id, title });
+        Ok(())
+    }
 
-        url.path_segments_mut().unwrap().pop().push("episodes");
-        for (series_idx, series) in response.data.into_iter().enumerate() {
-            let msg = format!("Processing tv show {}", series.title);
-            if self.is_tty {
-                pb_main.set_message(msg.clone());
-            } else {
-                println!("TV Show {}/{}: {}", series_idx + 1, num_series, msg);
-            }
+    pub async fn tv_shows(&self) -> Result<(), Error> {
+        self.report(Event::Started {
+            media_type: MediaType::TVShow,
+            action: &self.action,
+        });
+
+        let query = self.list_query();
+        let mut resume = self.resume.clone().filter(|c| c.series_id.is_some());
+        // tv shows are listed page by page, again from the start if the resumed one is not found
+        loop {
+            let mut seeking = resume.as_ref().and_then(|c| c.series_id);
+            let mut first_page = true;
+            let mut listed = 0;
+            let mut pages = pin!(self.client.series_pages(&query));
+            while let Some(page) = self.shutdown.until_aborted(pages.try_next()).await? {
+                self.track_progress(&query, &page, first_page);
+                first_page = false;
+                listed += page.records.len();
+
+                let mut series_list = page.records;
+                if let Some(series_id) = seeking {
+                    let Some(idx) = series_list
+                        .iter()
+                        .position(|s| s.sonarr_series_id == series_id)
+                    else {
+                        self.report(Event::Skipped {
+                            count: series_list.len() as u64,
+                        });
+                        continue;
+                    };
+                    series_list.drain(..idx);
+                    self.report(Event::Resuming {
+                        title: &series_list[0].title,
+                    });
+                    self.report(Event::Skipped { count: idx as u64 });
+                    seeking = None;
+                }
 
-            let query_param = format!("seriesid[]={}", series.sonarr_series_id);
-            let mut new_url = url.clone();
-            new_url.set_query(Some(&query_param));
-            let response = self.get_all::<Episode>(new_url).await?;
-            let mut episodes = response.data;
-            if self.skip_processed {
-                println!(
-                    "Processing {} episodes, checking for already processed ones...",
-                    episodes.len()
-                );
-                let initial_len = episodes.len();
-                episodes = filter_unprocessed_episodes(self.db_conn.clone(), episodes).await?;
-                let after_len = episodes.len();
-                let difference = initial_len - after_len;
-                if difference > 0 {
-                    self.log_info(
-                        &pb_main,
-                        format!("Skipped {difference} already processed episodes..."),
-                    );
-                } else {
-                    self.log_info(&pb_main, "No previously processed episodes");
+                for batch in series_list.chunks(self.series_per_request.max(1)) {
+                    if self.shutdown.is_requested() {
+                        break;
+                    }
+                    let ids: Vec<u32> = batch.iter().map(|s| s.sonarr_series_id).collect();
+                    let episodes = self
+                        .shutdown
+                        .until_aborted(self.client.episodes(&ids))
+                        .await?;
+                    let mut episodes_by_series = group_by_series(episodes);
+
+                    for series in batch {
+                        if self.shutdown.is_requested() {
+                            break;
+                        }
+                        let episodes = episodes_by_series
+                            .remove(&series.sonarr_series_id)
+                            .unwrap_or_default();
+                        // only the first tv show of a resumed run can be partially completed
+                        self.process_series(series, episodes, resume.take()).await?;
+                    }
                 }
+                if self.shutdown.is_requested() {
+                    break;
+                }
+            }
+
+            if listed == 0 {
+                self.report(Event::NothingFound);
+                return Ok(());
             }
-            let num_episodes: u64 = episodes.len() as u64;
-            sub_pb.set_position(0);
-            sub_pb.set_length(num_episodes);
-            if num_episodes == 0 {
-                self.finish(&sub_pb, "No episodes found");
+            if seeking.is_some() && !self.shutdown.is_requested() {
+                self.report(Event::ResumeNotFound);
+                resume = None;
                 continue;
             }
+            break;
+        }
 
-            if !self.is_tty {
-                println!("  Processing {} episodes...", num_episodes);
-            }
+        self.report_finished();
+        Ok(())
+    }
+}
 
-            for (ep_idx, episode) in episodes.into_iter().enumerate() {
-                if !self.is_tty {
-                    println!("    Episode {}/{}", ep_idx + 1, num_episodes);
-                }
-                self.process_episode_subtitle(&sub_pb, episode).await;
-                sub_pb.inc(1);
-            }
-            pb_main.inc(1);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(series_id: u32, episode_id: u32) -> Episode {
+        serde_json::from_value(serde_json::json!({
+            "sonarrSeriesId": series_id,
+            "sonarrEpisodeId": episode_id,
+            "title": format!("Episode {episode_id}"),
+            "subtitles": [
+                {"name": "English", "code2": "en", "path": "/en.srt"},
+                {"name": "French", "code2": "fr", "path": "/fr.srt"},
+                {"name": "German", "code2": "de", "path": "/de.srt"},
+            ],
+        }))
+        .unwrap()
+    }
 
-            self.log_info(
-                &pb_main,
-                format!("Finished processing tv show {}", series.title),
-            );
-        }
+    fn episode_ids(episodes: &[Episode]) -> Vec<u32> {
+        episodes.iter().map(|e| e.sonarr_episode_id).collect()
+    }
 
-        self.finish(
-            &pb_main,
-            format!(
-                "Finished performing action {} on all tv shows",
-                self.action.to_string(),
-            ),
+    #[test]
+    fn groups_episodes_by_series() {
+        let episodes = vec![
+            episode(1, 10),
+            episode(2, 20),
+            episode(1, 11),
+            episode(2, 21),
+        ];
+        let mut grouped = group_by_series(episodes);
+        assert_eq!(grouped.len(), 2);
+        assert_eq!(episode_ids(&grouped.remove(&1).unwrap()), [10, 11]);
+        assert_eq!(episode_ids(&grouped.remove(&2).unwrap()), [20, 21]);
+        assert!(group_by_series(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn skips_completed_records() {
+        let mut episodes = vec![episode(1, 10), episode(1, 11), episode(1, 12)];
+        let completed = skip_completed(
+            &mut episodes,
+            11,
+            Some("fr"),
+            |e| e.sonarr_episode_id,
+            |e| &e.subtitles,
+        )
+        .unwrap();
+        assert_eq!(episode_ids(&episodes), [11, 12]);
+        let expected: ProcessedKeys = [(11, "en".to_string()), (11, "fr".to_string())].into();
+        assert_eq!(completed, expected);
+    }
+
+    #[test]
+    fn skips_nothing_when_the_record_is_gone() {
+        let mut episodes = vec![episode(1, 10), episode(1, 12)];
+        let completed = skip_completed(
+            &mut episodes,
+            11,
+            None,
+            |e| e.sonarr_episode_id,
+            |e| &e.subtitles,
         );
-        Ok(())
+        assert!(completed.is_none());
+        assert_eq!(episode_ids(&episodes), [10, 12]);
     }
 }
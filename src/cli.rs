@@ -1,50 +1,183 @@
-use clap::{Parser, Subcommand};
-use reqwest::{header, Client};
+use clap::{Parser, Subcommand, ValueEnum};
 use reqwest_middleware::ClientBuilder;
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, time::Duration};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Mutex;
 
-use crate::{
-    actions::Action, connection::check_health, data_types::app_config::AppConfig, db::init_db,
+use bazarr_bulk::{
+    actions::{Action, RunStats},
+    client::{BazarrClient, DEFAULT_PAGE_SIZE},
+    connection::{build_client, check_compatibility, check_health},
+    data_types::{
+        app_config::{mask_credentials, AppConfig},
+        request::{ActionCommands, MediaType},
+    },
+    db::{
+        self, ActionResult, HistoryDate, HistoryFilter, ProcessedRecord, RecordFilter,
+        RunCheckpoint, RunStatus,
+    },
+    error::Error,
+    rate_limit::{AdaptiveThrottle, Rate, RateLimitMiddleware},
+    retry::RetryMiddleware,
+    shutdown::Shutdown,
 };
 
+use crate::{console::ConsoleReporter, doctor, signals};
+
 #[derive(Parser)]
 #[command(name = "Bazarr Bulk Actions CLI")]
 #[command(author = "Mateo Radman <radmanmateo@gmail.com>")]
 #[command(about = "Performs bulk operations on subtitles of movies and tv shows using Bazarr's API", long_about = None)]
 pub struct Cli {
-    /// Path to the JSON configuration file
-    #[arg(required = true, short, long, value_name = "FILE")]
-    pub config: PathBuf,
+    #[command(flatten)]
+    pub options: GlobalOptions,
+
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+impl Cli {
+    pub async fn run(self) -> Result<(), Error> {
+        eprintln!("Bazarr Bulk CLI v{}", env!("CARGO_PKG_VERSION"));
+        self.command.run(self.options).await
+    }
+}
+
+#[derive(clap::Args)]
+pub struct GlobalOptions {
+    /// Path to the configuration file (JSON, TOML or YAML).
+    /// Required for movies and tv-shows unless configured through BB_* environment variables
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
 
     /// Path to the SQLite database file
     /// If not specified, uses BB_DATA_DIR environment variable or default user data directory
     #[arg(long, value_name = "FILE")]
     pub db_path: Option<PathBuf>,
 
-    /// Number of times to retry the request in case of lost connection
+    /// Number of times to retry read requests after a transient error
+    /// (lost connection, timeout, server error or rate limiting)
     #[arg(short, long, default_value_t = 3)]
     pub max_retries: u32,
 
-    /// Duration of the retry interval (seconds)
+    /// Number of times to retry actions after a transient error.
+    /// Retried actions may run twice on Bazarr, so by default they are
+    /// only retried when the request could not reach Bazarr
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub action_retries: u32,
+
+    /// Initial retry interval, doubled after each retry with random jitter (seconds)
     #[arg(short, long, default_value_t = 10)]
     pub retry_interval: u64,
 
-    #[command(subcommand)]
-    pub command: Commands,
+    /// Maximum retry interval (seconds).
+    /// Requests are not retried when Bazarr asks to wait longer with Retry-After
+    #[arg(long, value_name = "SECONDS", default_value_t = 120)]
+    pub max_retry_interval: u64,
+
+    /// Seconds to wait for a connection to Bazarr, 0 to wait indefinitely
+    /// [default: connectTimeout of the configuration, or 10]
+    #[arg(long, value_name = "SECONDS")]
+    pub connect_timeout: Option<u64>,
+
+    /// Seconds to wait for a response from Bazarr, 0 to wait indefinitely
+    /// [default: requestTimeout of the configuration, or 600]
+    #[arg(long, value_name = "SECONDS")]
+    pub request_timeout: Option<u64>,
+
+    /// HTTP, HTTPS or SOCKS5 proxy to send requests through, e.g. socks5://127.0.0.1:1080
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Additional header sent with every request (can be repeated)
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Maximum rate of requests to Bazarr, e.g. 2/s or 30/m [default: unlimited]
+    #[arg(long, value_name = "RATE")]
+    pub rate: Option<Rate>,
+
+    /// Adapt the request rate to keep the latency of actions under the given number of seconds,
+    /// up to --rate [default: 10/s]
+    #[arg(long, value_name = "SECONDS")]
+    pub target_latency: Option<f64>,
+
+    /// Seconds to wait between two actions, so Bazarr can catch up on its own tasks
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0)]
+    pub pause: f64,
+
+    /// Number of movies or TV shows requested from Bazarr at once.
+    /// Processing starts after the first page instead of after listing the whole library
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_PAGE_SIZE,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub page_size: u32,
+
+    /// Number of TV shows whose episodes are requested from Bazarr at once
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub series_per_request: u32,
+
+    /// Name of the Bazarr instance to use when the configuration defines several
+    #[arg(long, value_name = "NAME")]
+    pub instance: Option<String>,
+
+    /// Run the command against every Bazarr instance of the configuration in turn
+    #[arg(long, default_value_t = false, conflicts_with = "instance")]
+    pub all_instances: bool,
+
+    /// Start actions even if the Bazarr version is known not to support them
+    #[arg(long, default_value_t = false)]
+    pub skip_version_check: bool,
+
+    /// Seconds to wait for the current subtitle to finish after Ctrl-C or SIGTERM
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    pub shutdown_timeout: u64,
+
+    /// Save the raw body of responses that fail to decode in the debug directory next to the database
+    #[arg(long, default_value_t = false)]
+    pub debug: bool,
 }
 
-impl Cli {
-    pub async fn run(self, config: AppConfig) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Bazarr Bulk CLI v{}", env!("CARGO_PKG_VERSION"));
-        self.command
-            .run(config, self.max_retries, self.retry_interval, self.db_path)
-            .await
+impl GlobalOptions {
+    /// Override the connection settings of the configuration with the command line options
+    pub fn apply_overrides(&self, config: &mut AppConfig) {
+        if let Some(timeout) = self.connect_timeout {
+            config.connect_timeout = timeout;
+        }
+        if let Some(timeout) = self.request_timeout {
+            config.request_timeout = timeout;
+        }
+        if let Some(proxy) = &self.proxy {
+            config.proxy = Some(proxy.clone());
+        }
+        config.headers.extend(self.headers.iter().cloned());
     }
 }
 
-#[derive(clap::Args)]
+/// Parse a `Name: value` header argument
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("'{header}' is not in the form 'Name: value'"))?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+#[derive(clap::Args, Clone, Serialize, Deserialize)]
 pub struct CommonArgs {
     /// Filter records by Sonarr/Radarr ID (comma-separated)
     #[arg(long, required = false, value_delimiter = ',')]
@@ -55,16 +188,24 @@ pub struct CommonArgs {
     /// Limit to N records (ignored if ids are specified) [default: unlimited]
     #[arg(long)]
     limit: Option<u32>,
-    /// Skip already processed as queried from the db.
-    /// Must have all subtitles processed to be skipped.
+    /// Skip already processed subtitles as queried from the db.
+    /// Records are skipped entirely once all of their subtitles are processed.
     #[arg(long, default_value_t = false, required = false)]
     skip_processed: bool,
     /// Filter subtitles by language code (e.g., en, es, fr)
     #[arg(long, short = 'l')]
     language: Option<String>,
+    /// Continue the last interrupted run with identical parameters
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["ids", "offset", "limit", "skip_processed", "language"]
+    )]
+    #[serde(skip)]
+    resume: bool,
     /// List available actions
     #[command(subcommand)]
-    subcommand: ActionCommands,
+    subcommand: Option<ActionCommands>,
 }
 
 #[derive(Subcommand)]
@@ -73,102 +214,654 @@ pub enum Commands {
     Movies(CommonArgs),
     /// Perform operations on tv shows
     TVShows(CommonArgs),
+    /// Inspect and manage the database of processed subtitles
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// Show the log of performed actions, most recent first
+    History(HistoryArgs),
+    /// Diagnose the connection to Bazarr and the database
+    Doctor,
 }
 
 impl Commands {
-    pub async fn run(
-        self,
-        config: AppConfig,
-        max_retries: u32,
-        retry_interval: u64,
-        db_path: Option<PathBuf>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut headers = header::HeaderMap::new();
-        headers.insert(
-            "X-API-KEY",
-            header::HeaderValue::from_str(&config.api_key).unwrap(),
+    pub async fn run(self, options: GlobalOptions) -> Result<(), Error> {
+        let (c, media_type) = match self {
+            Commands::Movies(c) => (c, MediaType::Movie),
+            Commands::TVShows(c) => (c, MediaType::TVShow),
+            Commands::Db { command } => {
+                let db_conn = open_db(&options).await?;
+                return command.run(db_conn).await;
+            }
+            Commands::History(args) => {
+                let db_conn = open_db(&options).await?;
+                return args.run(db_conn).await;
+            }
+            Commands::Doctor => return doctor::run(&options).await,
+        };
+
+        if c.resume && c.subcommand.is_some() {
+            return Err(Error::Config(
+                "An action cannot be specified when resuming a run".to_string(),
+            ));
+        }
+        if !c.resume && c.subcommand.is_none() {
+            return Err(Error::Config(
+                "An action must be specified, see --help for available actions".to_string(),
+            ));
+        }
+
+        let configs = if options.all_instances {
+            AppConfig::all(options.config.as_deref())?
+        } else {
+            vec![AppConfig::new(
+                options.config.as_deref(),
+                options.instance.as_deref(),
+            )?]
+        };
+        let db_conn = open_db(&options).await?;
+        let shutdown = signals::listen();
+        let total = RunStats::default();
+        let instance_count = configs.len();
+        let mut failed = Vec::new();
+        for config in configs {
+            if shutdown.is_requested() {
+                break;
+            }
+            let instance = config.instance_id()?;
+            if options.all_instances {
+                println!("\n=== Instance {instance} ===");
+            }
+            let result = run_instance(
+                config,
+                c.clone(),
+                media_type,
+                &options,
+                db_conn.clone(),
+                shutdown.clone(),
+                &total,
+            )
+            .await;
+            match result {
+                Ok(()) => {}
+                // the other instances keep running unless the whole run is aborted
+                Err(e) if options.all_instances && !matches!(e, Error::Interrupted) => {
+                    eprintln!("Error: {e}");
+                    failed.push((instance, e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if options.all_instances {
+            println!(
+                "\nCombined summary of {instance_count} instance(s): {}",
+                total.summary(shutdown.is_requested())
+            );
+        }
+        if shutdown.is_requested() {
+            return Err(Error::Interrupted);
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InstancesFailed {
+                failed,
+                total: instance_count,
+            })
+        }
+    }
+}
+
+/// Open the database, creating it on first use
+async fn open_db(options: &GlobalOptions) -> Result<Arc<Mutex<Connection>>, Error> {
+    let db_path = db::get_db_path(options.db_path.clone()).map_err(Error::Config)?;
+    eprintln!("Using database at: {}", db_path.display());
+    Ok(db::init_db(Some(db_path)).await?)
+}
+
+/// Run the action against a single Bazarr instance
+async fn run_instance(
+    mut config: AppConfig,
+    c: CommonArgs,
+    media_type: MediaType,
+    options: &GlobalOptions,
+    db_conn: Arc<Mutex<Connection>>,
+    shutdown: Shutdown,
+    total: &RunStats,
+) -> Result<(), Error> {
+    options.apply_overrides(&mut config);
+
+    if !options.pause.is_finite() || options.pause < 0.0 {
+        return Err(Error::Config(
+            "--pause must be a non-negative number of seconds".to_string(),
+        ));
+    }
+    if options.max_retry_interval < options.retry_interval {
+        return Err(Error::Config(
+            "--max-retry-interval must not be lower than --retry-interval".to_string(),
+        ));
+    }
+    if options
+        .target_latency
+        .is_some_and(|target| !target.is_finite() || target <= 0.0)
+    {
+        return Err(Error::Config(
+            "--target-latency must be a positive number of seconds".to_string(),
+        ));
+    }
+    let reqwest_client = build_client(&config)?;
+    if config.insecure_skip_verify {
+        eprintln!("Warning: TLS certificate verification is disabled (insecureSkipVerify)");
+    }
+    let limiter = Arc::new(RateLimitMiddleware::new(
+        options.rate,
+        Duration::from_secs_f64(options.pause),
+    ));
+    let throttle = options.target_latency.map(|target| {
+        Arc::new(AdaptiveThrottle::new(
+            limiter.clone(),
+            Duration::from_secs_f64(target),
+            options.rate.unwrap_or(Rate { per_second: 10.0 }),
+        ))
+    });
+    let client = ClientBuilder::new(reqwest_client)
+        .with(RetryMiddleware {
+            min_retry_interval: Duration::from_secs(options.retry_interval),
+            max_retry_interval: Duration::from_secs(options.max_retry_interval),
+            read_retries: options.max_retries,
+            action_retries: options.action_retries,
+        })
+        .with_arc(limiter)
+        .build();
+    let mut client =
+        BazarrClient::new(client, config.construct_url()?).with_page_size(options.page_size);
+    if options.debug {
+        let db_path = db::get_db_path(options.db_path.clone()).map_err(Error::Config)?;
+        client = client.with_debug_dir(db_path.with_file_name("debug"));
+    }
+    println!("Bazarr API URL: {}", mask_credentials(client.base_url()));
+    let version = match check_health(&client).await {
+        Ok(Some(version)) => {
+            println!("Bazarr API is healthy (Bazarr version {version}).");
+            Some(version)
+        }
+        Ok(None) => {
+            println!("Bazarr API is healthy.");
+            None
+        }
+        Err(e @ Error::Status { .. }) => {
+            eprintln!("Error while checking the health of Bazarr: {e}");
+            println!("Attempting to continue anyway...");
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let instance = config.instance_id()?;
+    let unassigned = db::count_unassigned_records(db_conn.clone()).await?;
+    if unassigned > 0 {
+        eprintln!(
+            "Warning: {unassigned} subtitle(s) processed before instances were tracked belong to no instance and are not skipped. Run `bb db assign {instance}` to assign them to this instance"
+        );
+    }
+    let mut action = Action::new(client, instance.clone(), db_conn.clone());
+    action.reporter = Arc::new(ConsoleReporter::new());
+    action.shutdown = shutdown;
+    action.shutdown_timeout = Duration::from_secs(options.shutdown_timeout);
+    action.series_per_request = options.series_per_request as usize;
+    if let Some(throttle) = &throttle {
+        println!(
+            "Adaptive throttling: keeping actions under {:.1}s, at most {}",
+            throttle.target_latency.as_secs_f64(),
+            throttle.max_rate
+        );
+    }
+    action.throttle = throttle;
+    let result = run_action(&mut action, c, media_type, options, version.as_deref()).await;
+    // subtitles processed before a failure count towards the combined summary
+    total.add(&action.stats);
+    result
+}
+
+/// Start the run, or resume the last interrupted one, and record how it ended
+async fn run_action(
+    action: &mut Action,
+    mut c: CommonArgs,
+    media_type: MediaType,
+    options: &GlobalOptions,
+    version: Option<&str>,
+) -> Result<(), Error> {
+    let db_conn = action.db_conn.clone();
+    let instance = action.instance.clone();
+    if c.resume {
+        let Some(checkpoint) =
+            db::last_unfinished_run(db_conn.clone(), instance.clone(), media_type).await?
+        else {
+            if options.all_instances {
+                println!("No interrupted {media_type} run found to resume, skipping instance");
+                return Ok(());
+            }
+            return Err(Error::NothingToResume(media_type));
+        };
+        println!(
+            "Resuming run {} started at {} (UTC)",
+            checkpoint.run_id,
+            db::format_timestamp(checkpoint.started_at)
+        );
+        c = serde_json::from_str(&checkpoint.parameters).map_err(|e| Error::Decode {
+            context: format!("the parameters of run {}", checkpoint.run_id),
+            message: e.to_string(),
+        })?;
+        action.run_id = checkpoint.run_id.clone();
+        action.resume = Some(checkpoint);
+    }
+    // validated above for new runs, and stored in the parameters of resumed runs
+    let action_command = c
+        .subcommand
+        .clone()
+        .ok_or_else(|| Error::Config("The resumed run has no action".to_string()))?;
+    if let Some(warning) =
+        check_compatibility(version, &action_command, options.skip_version_check)?
+    {
+        eprintln!("Warning: {warning}");
+    }
+    db::start_run(
+        db_conn.clone(),
+        RunCheckpoint {
+            run_id: action.run_id.clone(),
+            instance,
+            media_type,
+            parameters: serde_json::to_string(&c).map_err(|e| Error::Encode {
+                context: "the parameters of the run".to_string(),
+                message: e.to_string(),
+            })?,
+            series_id: None,
+            media_id: None,
+            language_code: None,
+            started_at: db::now(),
+        },
+    )
+    .await?;
+
+    action.action = action_command;
+    action.ids = c.ids;
+    action.limit = c.limit;
+    action.offset = c.offset;
+    action.skip_processed = c.skip_processed;
+    action.language_code = c.language;
+    let result = match media_type {
+        MediaType::Movie => action.movies().await,
+        MediaType::TVShow => action.tv_shows().await,
+    };
+    if let Err(Error::Interrupted) = result {
+        db::finish_run(db_conn, action.run_id.clone(), RunStatus::Interrupted).await?;
+        return Err(Error::Interrupted);
+    }
+    result?;
+    // a graceful stop fails the command once the summaries are printed
+    if action.shutdown.is_requested() {
+        db::finish_run(db_conn, action.run_id.clone(), RunStatus::Interrupted).await?;
+    } else {
+        db::finish_run(db_conn, action.run_id.clone(), RunStatus::Completed).await?;
+    }
+    Ok(())
+}
+
+#[derive(clap::Args)]
+pub struct RecordFilterArgs {
+    /// Only include records of the given Bazarr instance (configured name or URL)
+    #[arg(long)]
+    instance: Option<String>,
+    /// Only include records of the given media type
+    #[arg(long, short = 't')]
+    media_type: Option<MediaType>,
+    /// Filter records by Sonarr episode/Radarr movie ID (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    ids: Vec<u32>,
+    /// Filter records whose title contains the given text
+    #[arg(long)]
+    title: Option<String>,
+    /// Filter records by language code (e.g., en, es, fr)
+    #[arg(long, short = 'l')]
+    language: Option<String>,
+    /// Filter records by action (e.g., sync, OCR_fixes, common, remove_HI)
+    #[arg(long, short = 'a')]
+    action: Option<String>,
+}
+
+impl From<RecordFilterArgs> for RecordFilter {
+    fn from(args: RecordFilterArgs) -> Self {
+        RecordFilter {
+            instance: args.instance,
+            media_type: args.media_type,
+            ids: args.ids,
+            title: args.title,
+            language: args.language,
+            action: args.action,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+pub struct HistoryArgs {
+    /// Only include actions of the given run
+    #[arg(long)]
+    run: Option<String>,
+    /// Only include actions performed on the given Bazarr instance (configured name or URL)
+    #[arg(long)]
+    instance: Option<String>,
+    /// Only include actions on the given media type
+    #[arg(long, short = 't')]
+    media_type: Option<MediaType>,
+    /// Only include actions on media whose title contains the given text
+    #[arg(long)]
+    title: Option<String>,
+    /// Only include actions with the given result
+    #[arg(long)]
+    result: Option<ActionResult>,
+    /// Only include actions performed on or after the given date (YYYY-MM-DD[ HH:MM:SS], UTC)
+    #[arg(long, value_name = "DATE")]
+    since: Option<HistoryDate>,
+    /// Only include actions performed on or before the given date (YYYY-MM-DD[ HH:MM:SS], UTC)
+    #[arg(long, value_name = "DATE")]
+    until: Option<HistoryDate>,
+    /// Limit to N entries [default: 50]
+    #[arg(long, default_value_t = 50)]
+    limit: u32,
+    /// Show the error message of failed actions
+    #[arg(long, default_value_t = false)]
+    errors: bool,
+}
+
+impl HistoryArgs {
+    pub async fn run(self, db_conn: Arc<Mutex<Connection>>) -> Result<(), Error> {
+        let filter = HistoryFilter {
+            run_id: self.run,
+            instance: self.instance,
+            media_type: self.media_type,
+            title: self.title,
+            result: self.result,
+            since: self.since,
+            until: self.until,
+        };
+        let entries = db::query_history(db_conn, filter, Some(self.limit)).await?;
+        if entries.is_empty() {
+            println!("No actions found");
+            return Ok(());
+        }
+
+        println!(
+            "{:<20} {:<20} {:<14} {:<8} {:>8}  {:<5} {:<16} {:>5} {:>9}  Title",
+            "Performed at (UTC)",
+            "Run",
+            "Action",
+            "Type",
+            "ID",
+            "Lang",
+            "Result",
+            "HTTP",
+            "Duration"
         );
-        let min_retry_interval = Duration::new(retry_interval, 0);
-        let max_retry_interval = Duration::new(retry_interval + 1, 0);
-        let retry_policy = ExponentialBackoff::builder()
-            .retry_bounds(min_retry_interval, max_retry_interval)
-            .build_with_max_retries(max_retries);
-        let reqwest_client = Client::builder().default_headers(headers).build()?;
-        let client = ClientBuilder::new(reqwest_client)
-            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-            .build();
-        let url = config.construct_url();
-        check_health(&client, &url).await;
-        let db_conn = init_db(db_path).await?;
-        let mut action = Action::new(client, url, db_conn);
+        for entry in &entries {
+            println!(
+                "{:<20} {:<20} {:<14} {:<8} {:>8}  {:<5} {:<16} {:>5} {:>7}ms  {}",
+                db::format_timestamp(entry.performed_at),
+                entry.run_id,
+                entry.action,
+                entry.media_type.to_string(),
+                entry.media_id,
+                entry.language_code.as_deref().unwrap_or("-"),
+                entry.result.to_string(),
+                entry
+                    .http_status
+                    .map_or_else(|| "-".to_string(), |status| status.to_string()),
+                entry.duration_ms,
+                entry.title,
+            );
+            if self.errors {
+                if let Some(error) = &entry.error {
+                    println!("{:>22}{}", "", error);
+                }
+            }
+        }
+        println!("\nShowing {} action(s)", entries.len());
+        Ok(())
+    }
+}
+
+#[derive(Subcommand)]
+pub enum DbCommands {
+    /// Show the number of processed subtitles per media type, action and language
+    Stats,
+    /// List processed subtitles, most recent first
+    List {
+        #[command(flatten)]
+        filter: RecordFilterArgs,
+        /// Limit to N records [default: 50]
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        /// Show the subtitle file path of each record
+        #[arg(long, default_value_t = false)]
+        paths: bool,
+    },
+    /// Delete processed subtitles matching the filters so they get processed again
+    Forget(RecordFilterArgs),
+    /// Delete all processed subtitles
+    Reset {
+        /// Confirm deleting all records
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+    /// Rebuild the database file to reclaim unused space
+    Vacuum,
+    /// Assign subtitles processed before instances were tracked to a Bazarr instance
+    Assign {
+        /// Bazarr instance the subtitles were processed on (configured name or URL)
+        #[arg(value_name = "INSTANCE")]
+        instance: String,
+    },
+    /// Export processed subtitles as JSON Lines or CSV
+    Export {
+        #[command(flatten)]
+        filter: RecordFilterArgs,
+        /// File to write the records to [default: stdout]
+        #[arg(long, short = 'o', value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Format of the exported records [default: inferred from the file extension, jsonl for stdout]
+        #[arg(long, short = 'f')]
+        format: Option<RecordFormat>,
+    },
+    /// Import processed subtitles from JSON Lines or CSV, keeping the newer record on conflict
+    Import {
+        /// File to read the records from ("-" for stdin)
+        #[arg(value_name = "FILE")]
+        input: PathBuf,
+        /// Format of the imported records [default: inferred from the file extension, jsonl for stdin]
+        #[arg(long, short = 'f')]
+        format: Option<RecordFormat>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+impl RecordFormat {
+    fn detect(path: Option<&Path>) -> Self {
+        match path
+            .and_then(|path| path.extension())
+            .and_then(|ext| ext.to_str())
+        {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => RecordFormat::Csv,
+            _ => RecordFormat::Jsonl,
+        }
+    }
+
+    fn write(self, writer: impl Write, records: &[ProcessedRecord]) -> Result<(), Error> {
         match self {
-            Commands::Movies(c) => {
-                action.action = c.subcommand;
-                action.ids = c.ids;
-                action.limit = c.limit;
-                action.offset = c.offset;
-                action.skip_processed = c.skip_processed;
-                action.language_code = c.language;
-                action.movies().await
-            }
-            Commands::TVShows(c) => {
-                action.action = c.subcommand;
-                action.ids = c.ids;
-                action.limit = c.limit;
-                action.offset = c.offset;
-                action.skip_processed = c.skip_processed;
-                action.language_code = c.language;
-                action.tv_shows().await
-            }
-        }
-    }
-}
-
-#[derive(Subcommand, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub enum ActionCommands {
-    /// Sync all
-    Sync(SyncOptions),
-    /// Perform OCR fixes
-    OCRFixes,
-    /// Perform common fixes
-    CommonFixes,
-    /// Remove hearing impaired tags from subtitles
-    RemoveHearingImpaired,
-    /// Remove style tags from subtitles
-    RemoveStyleTags,
-    /// Fix uppercase subtitles
-    FixUppercase,
-    /// Reverse RTL directioned subtitles
-    ReverseRTL,
-}
-
-#[allow(clippy::to_string_trait_impl)]
-impl ToString for ActionCommands {
-    fn to_string(&self) -> String {
+            RecordFormat::Jsonl => {
+                let mut writer = BufWriter::new(writer);
+                for record in records {
+                    serde_json::to_writer(&mut writer, record).map_err(io::Error::from)?;
+                    writer.write_all(b"\n")?;
+                }
+                writer.flush()?;
+            }
+            RecordFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                for record in records {
+                    writer.serialize(record).map_err(io::Error::from)?;
+                }
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(self, reader: impl Read) -> Result<Vec<ProcessedRecord>, Error> {
+        let mut records = Vec::new();
         match self {
-            ActionCommands::Sync(_) => "sync".to_string(),
-            ActionCommands::OCRFixes => "OCR_fixes".to_string(),
-            ActionCommands::CommonFixes => "common".to_string(),
-            ActionCommands::RemoveHearingImpaired => "remove_HI".to_string(),
-            ActionCommands::RemoveStyleTags => "remove_tags".to_string(),
-            ActionCommands::FixUppercase => "fix_uppercase".to_string(),
-            ActionCommands::ReverseRTL => "reverse_rtl".to_string(),
-        }
-    }
-}
-
-#[derive(clap::Args, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub struct SyncOptions {
-    /// Reference for sync from video file track number (a:0), subtitle (s:0), or some subtitles file path
-    #[arg(short)]
-    pub reference: Option<String>,
-    /// Seconds of offset allowed when syncing [default: null]
-    #[arg(short, value_name = "MAX OFFSET")]
-    pub max_offset_seconds: Option<u32>,
-    /// Do not attempt to fix framerate [default: false]
-    #[arg(short, default_value_t = false)]
-    pub no_fix_framerate: bool,
-    /// Use Golden-Section search [default: false]
-    #[arg(short, default_value_t = false)]
-    pub gss: bool,
+            RecordFormat::Jsonl => {
+                for (idx, line) in BufReader::new(reader).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let record = serde_json::from_str(&line).map_err(|e| Error::Decode {
+                        context: format!("the record on line {}", idx + 1),
+                        message: e.to_string(),
+                    })?;
+                    records.push(record);
+                }
+            }
+            RecordFormat::Csv => {
+                for record in csv::Reader::from_reader(reader).deserialize() {
+                    records.push(record.map_err(|e| Error::Decode {
+                        context: "a CSV record".to_string(),
+                        message: e.to_string(),
+                    })?);
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl DbCommands {
+    pub async fn run(self, db_conn: Arc<Mutex<Connection>>) -> Result<(), Error> {
+        match self {
+            DbCommands::Stats => {
+                let stats = db::stats(db_conn).await?;
+                let total: u64 = stats.by_media_type.iter().map(|(_, count)| count).sum();
+                println!("Processed subtitles: {total}");
+                println!("\nBy instance:");
+                for (instance, count) in stats.by_instance {
+                    println!("  {:<24} {:>8}", instance, count);
+                }
+                println!("\nBy media type:");
+                for (media_type, count) in stats.by_media_type {
+                    println!("  {:<24} {:>8}", media_type.to_string(), count);
+                }
+                println!("\nBy action:");
+                for (action, count) in stats.by_action {
+                    println!("  {:<24} {:>8}", action, count);
+                }
+                println!("\nBy language:");
+                for (language, count) in stats.by_language {
+                    println!("  {:<24} {:>8}", language, count);
+                }
+            }
+            DbCommands::List {
+                filter,
+                limit,
+                paths,
+            } => {
+                let records = db::list_records(db_conn, filter.into(), Some(limit)).await?;
+                if records.is_empty() {
+                    println!("No processed subtitles found");
+                    return Ok(());
+                }
+                println!(
+                    "{:<8} {:>8}  {:<20} {:<14} {:<20} Title",
+                    "Type", "ID", "Language", "Action", "Processed at (UTC)"
+                );
+                for record in &records {
+                    println!(
+                        "{:<8} {:>8}  {:<20} {:<14} {:<20} {}",
+                        record.media_type.to_string(),
+                        record.media_id,
+                        format!("{} ({})", record.language_code, record.language_name),
+                        record.action.as_deref().unwrap_or("unknown"),
+                        db::format_timestamp(record.processed_at),
+                        record.title,
+                    );
+                    if paths {
+                        println!("{:>18}{}", "", record.path.as_deref().unwrap_or("-"));
+                    }
+                }
+                println!("\nShowing {} record(s)", records.len());
+            }
+            DbCommands::Forget(filter) => {
+                let filter: RecordFilter = filter.into();
+                if filter.is_empty() {
+                    return Err(Error::Config("At least one filter is required. Use `db reset --yes` to delete all records".to_string()));
+                }
+                let deleted = db::forget_records(db_conn, filter).await?;
+                println!("Deleted {deleted} processed subtitle record(s)");
+            }
+            DbCommands::Reset { yes } => {
+                if !yes {
+                    return Err(Error::Config(
+                        "Refusing to delete all records without --yes".to_string(),
+                    ));
+                }
+                let deleted = db::reset(db_conn).await?;
+                println!("Deleted {deleted} processed subtitle record(s)");
+            }
+            DbCommands::Vacuum => {
+                let (before, after) = db::vacuum(db_conn).await?;
+                println!("Database vacuumed: {before} bytes -> {after} bytes");
+            }
+            DbCommands::Assign { instance } => {
+                let assigned = db::claim_unassigned_records(db_conn, instance.clone()).await?;
+                println!("Assigned {assigned} processed subtitle record(s) to instance {instance}");
+            }
+            DbCommands::Export {
+                filter,
+                output,
+                format,
+            } => {
+                let format = format.unwrap_or_else(|| RecordFormat::detect(output.as_deref()));
+                let records = db::list_records(db_conn, filter.into(), None).await?;
+                match &output {
+                    Some(path) => {
+                        format.write(File::create(path)?, &records)?;
+                        println!("Exported {} record(s) to {}", records.len(), path.display());
+                    }
+                    None => format.write(std::io::stdout().lock(), &records)?,
+                }
+            }
+            DbCommands::Import { input, format } => {
+                let records = if input.as_os_str() == "-" {
+                    format
+                        .unwrap_or(RecordFormat::Jsonl)
+                        .read(std::io::stdin().lock())?
+                } else {
+                    format
+                        .unwrap_or_else(|| RecordFormat::detect(Some(&input)))
+                        .read(File::open(&input)?)?
+                };
+                let summary = db::import_records(db_conn, records).await?;
+                println!(
+                    "Imported records: {} inserted, {} updated, {} unchanged",
+                    summary.inserted, summary.updated, summary.unchanged
+                );
+            }
+        }
+        Ok(())
+    }
 }
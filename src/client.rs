@@ -0,0 +1,511 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::{stream, Stream, TryStreamExt};
+use reqwest::{StatusCode, Url};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    connection::{build_client, error_for_status},
+    data_types::{
+        app_config::AppConfig,
+        request::{ActionCommands, ActionPayload},
+        response::{Episode, Movie, PaginatedResponse, SystemStatus, SystemStatusResponse, TVShow},
+    },
+    error::Error,
+};
+
+/// Number of records requested at once when listing the library page by page
+pub const DEFAULT_PAGE_SIZE: u32 = 250;
+
+/// Records to list, either by ID or as a slice of the whole library
+#[derive(Debug, Clone, Default)]
+pub struct ListQuery {
+    /// Only list the records with these Radarr/Sonarr IDs, ignoring `start` and `length`
+    pub ids: Vec<u32>,
+    /// Number of records to skip
+    pub start: u32,
+    /// Maximum number of records to list, all of them if not set
+    pub length: Option<u32>,
+}
+
+impl ListQuery {
+    /// Number of records selected out of the `total` records matching the filters
+    pub fn selected(&self, total: u32) -> u32 {
+        if !self.ids.is_empty() {
+            return total;
+        }
+        let total = total.saturating_sub(self.start);
+        self.length.map_or(total, |length| total.min(length))
+    }
+}
+
+/// Records returned by a single request of a paginated listing
+#[derive(Debug)]
+pub struct Page<T> {
+    pub records: Vec<T>,
+    /// Number of records matching the filters, if reported by Bazarr
+    pub total: Option<u32>,
+}
+
+/// Typed async client of the Bazarr API
+#[derive(Clone)]
+pub struct BazarrClient {
+    client: ClientWithMiddleware,
+    base_url: Url,
+    page_size: u32,
+    debug_dir: Option<PathBuf>,
+}
+
+impl BazarrClient {
+    /// Send requests through `client` to the Bazarr API at `base_url`,
+    /// e.g. `http://localhost:6767/api`
+    pub fn new(client: ClientWithMiddleware, base_url: Url) -> Self {
+        Self {
+            client,
+            base_url,
+            page_size: DEFAULT_PAGE_SIZE,
+            debug_dir: None,
+        }
+    }
+
+    /// Request `page_size` records at once when listing the library
+    pub fn with_page_size(mut self, page_size: u32) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Save the raw body of responses that fail to decode in `dir`
+    pub fn with_debug_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.debug_dir = Some(dir.into());
+        self
+    }
+
+    /// Connect to the Bazarr instance of the configuration, without retries or rate limiting
+    pub fn from_config(config: &AppConfig) -> Result<Self, Error> {
+        let client = ClientBuilder::new(build_client(config)?).build();
+        Ok(Self::new(client, config.construct_url()?))
+    }
+
+    /// URL of the Bazarr API
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    fn endpoint(&self, path: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut().unwrap().extend(path);
+        url
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: Url) -> Result<T, Error> {
+        let endpoint = url.path().to_string();
+        let response = error_for_status(self.client.get(url).send().await?).await?;
+        let body = response.bytes().await?;
+        let mut deserializer = serde_json::Deserializer::from_slice(&body);
+        serde_path_to_error::deserialize(&mut deserializer)
+            .map_err(|e| (e.path().to_string(), e.into_inner()))
+            // trailing characters are not part of any field
+            .and_then(|value| {
+                deserializer
+                    .end()
+                    .map(|()| value)
+                    .map_err(|e| (".".to_string(), e))
+            })
+            .map_err(|(field, e)| {
+                let saved = self.save_body(&endpoint, &body);
+                Error::InvalidResponse {
+                    endpoint,
+                    field: (field != ".").then_some(field),
+                    message: e.to_string(),
+                    saved_to: saved.as_ref().and_then(|r| r.as_ref().ok()).cloned(),
+                    save_error: saved.and_then(Result::err),
+                }
+            })
+    }
+
+    /// Save the raw body of a response that could not be decoded, if a debug directory is set
+    fn save_body(&self, endpoint: &str, body: &[u8]) -> Option<Result<PathBuf, String>> {
+        let dir = self.debug_dir.as_deref()?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let name = endpoint.trim_matches('/').replace('/', "_");
+        let path = dir.join(format!("{name}-{timestamp}.json"));
+        Some(
+            fs::create_dir_all(dir)
+                .and_then(|()| fs::write(&path, body))
+                .map(|()| path)
+                .map_err(|e| e.to_string()),
+        )
+    }
+
+    /// Status of the Bazarr server, including its version
+    pub async fn status(&self) -> Result<SystemStatus, Error> {
+        let url = self.endpoint(&["system", "status"]);
+        Ok(self.get::<SystemStatusResponse>(url).await?.data)
+    }
+
+    /// Stream the records of a paginated endpoint, requesting one page at a time.
+    /// Records selected by ID are fetched with a single request.
+    fn pages<T: DeserializeOwned>(
+        &self,
+        path: &'static str,
+        id_param: &'static str,
+        query: &ListQuery,
+    ) -> impl Stream<Item = Result<Page<T>, Error>> + '_ {
+        let query = query.clone();
+        // start and number of records left to list of the next page
+        let first = Some((query.start, query.length));
+        stream::try_unfold(first, move |cursor| {
+            let ids = query.ids.clone();
+            async move {
+                let Some((start, remaining)) = cursor else {
+                    return Ok(None);
+                };
+                let mut url = self.endpoint(&[path]);
+                if !ids.is_empty() {
+                    for id in &ids {
+                        url.query_pairs_mut().append_pair(id_param, &id.to_string());
+                    }
+                    let response: PaginatedResponse<T> = self.get(url).await?;
+                    let page = Page {
+                        records: response.data,
+                        total: response.total,
+                    };
+                    return Ok(Some((page, None)));
+                }
+
+                let length = remaining.map_or(self.page_size, |r| r.min(self.page_size));
+                if length == 0 {
+                    return Ok(None);
+                }
+                url.query_pairs_mut()
+                    .append_pair("start", &start.to_string())
+                    .append_pair("length", &length.to_string());
+                let response: PaginatedResponse<T> = self.get(url).await?;
+                let count = response.data.len() as u32;
+                // a short page is the last one, as is a page ending at the largest offset
+                let next = start
+                    .checked_add(count)
+                    .filter(|&end| {
+                        count == length && response.total.is_none_or(|total| end < total)
+                    })
+                    .map(|end| (end, remaining.map(|r| r - count)));
+                let page = Page {
+                    records: response.data,
+                    total: response.total,
+                };
+                Ok(Some((page, next)))
+            }
+        })
+    }
+
+    /// Stream the movies of the library with their subtitles, one page at a time
+    pub fn movie_pages(
+        &self,
+        query: &ListQuery,
+    ) -> impl Stream<Item = Result<Page<Movie>, Error>> + '_ {
+        self.pages("movies", "radarrid[]", query)
+    }
+
+    /// Stream the TV shows of the library, one page at a time
+    pub fn series_pages(
+        &self,
+        query: &ListQuery,
+    ) -> impl Stream<Item = Result<Page<TVShow>, Error>> + '_ {
+        self.pages("series", "seriesid[]", query)
+    }
+
+    /// Movies of the library with their subtitles
+    pub async fn movies(&self, query: &ListQuery) -> Result<Vec<Movie>, Error> {
+        self.movie_pages(query)
+            .map_ok(|page| page.records)
+            .try_concat()
+            .await
+    }
+
+    /// TV shows of the library
+    pub async fn series(&self, query: &ListQuery) -> Result<Vec<TVShow>, Error> {
+        self.series_pages(query)
+            .map_ok(|page| page.records)
+            .try_concat()
+            .await
+    }
+
+    /// Episodes of the given TV shows with their subtitles
+    pub async fn episodes(&self, series_ids: &[u32]) -> Result<Vec<Episode>, Error> {
+        let mut url = self.endpoint(&["episodes"]);
+        for id in series_ids {
+            url.query_pairs_mut()
+                .append_pair("seriesid[]", &id.to_string());
+        }
+        Ok(self.get::<PaginatedResponse<Episode>>(url).await?.data)
+    }
+
+    /// Perform an action on a subtitle and return the HTTP status of Bazarr's response
+    pub async fn perform(
+        &self,
+        action: &ActionCommands,
+        mut payload: ActionPayload,
+    ) -> Result<StatusCode, Error> {
+        let mut url = self.endpoint(&["subtitles"]);
+        url.query_pairs_mut()
+            .append_pair("action", &action.to_string());
+        if let ActionCommands::Sync(sync_options) = action {
+            payload.reference = sync_options.reference.clone();
+            payload.max_offset_seconds = sync_options.max_offset_seconds;
+            payload.no_fix_framerate = Some(sync_options.no_fix_framerate);
+            payload.gss = Some(sync_options.gss);
+        }
+        let body = serde_json::to_vec(&payload).map_err(|e| Error::Encode {
+            context: format!("the payload of action {}", action.to_string()),
+            message: e.to_string(),
+        })?;
+        let response = self
+            .client
+            .patch(url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        Ok(error_for_status(response).await?.status())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Reply, TestServer};
+
+    /// Bazarr with `count` movies, reporting the total only if `with_total`
+    async fn library(count: u32, with_total: bool) -> (TestServer, BazarrClient) {
+        let server = TestServer::start(move |request| {
+            let param = |name| {
+                request
+                    .query(name)
+                    .first()
+                    .map(|v| v.parse::<u32>().unwrap())
+            };
+            let ids: Vec<u32> = request
+                .query("radarrid[]")
+                .iter()
+                .map(|id| id.parse().unwrap())
+                .collect();
+            let selected: Vec<u32> = if ids.is_empty() {
+                let start = param("start").unwrap_or(0);
+                let length = param("length").unwrap_or(count);
+                (1..=count)
+                    .skip(start as usize)
+                    .take(length as usize)
+                    .collect()
+            } else {
+                ids
+            };
+            let movies: Vec<String> = selected
+                .iter()
+                .map(|id| {
+                    format!(r#"{{"radarrId": {id}, "title": "Movie {id}", "subtitles": []}}"#)
+                })
+                .collect();
+            let total = if with_total {
+                format!(r#", "total": {count}"#)
+            } else {
+                String::new()
+            };
+            Reply::json(format!(r#"{{"data": [{}]{total}}}"#, movies.join(",")))
+        })
+        .await;
+        let client = BazarrClient::new(
+            ClientBuilder::new(reqwest::Client::new()).build(),
+            server.url.clone(),
+        )
+        .with_page_size(2);
+        (server, client)
+    }
+
+    async fn movie_ids(client: &BazarrClient, query: ListQuery) -> Vec<u32> {
+        let movies = client.movies(&query).await.unwrap();
+        movies.iter().map(|movie| movie.radarr_id).collect()
+    }
+
+    #[tokio::test]
+    async fn stops_after_a_short_page() {
+        let (server, client) = library(5, true).await;
+        assert_eq!(
+            movie_ids(&client, ListQuery::default()).await,
+            [1, 2, 3, 4, 5]
+        );
+        assert_eq!(
+            server.targets(),
+            [
+                "/api/movies?start=0&length=2",
+                "/api/movies?start=2&length=2",
+                "/api/movies?start=4&length=2",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_total() {
+        let (server, client) = library(4, true).await;
+        assert_eq!(movie_ids(&client, ListQuery::default()).await, [1, 2, 3, 4]);
+        assert_eq!(server.targets().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_at_an_empty_page_without_total() {
+        let (server, client) = library(4, false).await;
+        assert_eq!(movie_ids(&client, ListQuery::default()).await, [1, 2, 3, 4]);
+        assert_eq!(server.targets().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_requested_length() {
+        let (server, client) = library(10, true).await;
+        let query = ListQuery {
+            start: 1,
+            length: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(movie_ids(&client, query).await, [2, 3, 4]);
+        assert_eq!(
+            server.targets(),
+            [
+                "/api/movies?start=1&length=2",
+                "/api/movies?start=3&length=1",
+            ]
+        );
+
+        let query = ListQuery {
+            length: Some(0),
+            ..Default::default()
+        };
+        assert!(movie_ids(&client, query).await.is_empty());
+        assert_eq!(server.targets().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_largest_offset() {
+        // Bazarr answering every request with a full page
+        let server = TestServer::start(|_| {
+            Reply::json(
+                r#"{"data": [
+                    {"radarrId": 1, "title": "Movie 1", "subtitles": []},
+                    {"radarrId": 2, "title": "Movie 2", "subtitles": []}
+                ]}"#,
+            )
+        })
+        .await;
+        let client = BazarrClient::new(
+            ClientBuilder::new(reqwest::Client::new()).build(),
+            server.url.clone(),
+        )
+        .with_page_size(2);
+        let query = ListQuery {
+            start: u32::MAX - 3,
+            ..Default::default()
+        };
+        assert_eq!(movie_ids(&client, query).await, [1, 2, 1, 2]);
+        assert_eq!(
+            server.targets(),
+            [
+                "/api/movies?start=4294967292&length=2",
+                "/api/movies?start=4294967294&length=2",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_ids_with_a_single_request() {
+        let (server, client) = library(10, true).await;
+        let query = ListQuery {
+            ids: vec![3, 7, 9],
+            ..Default::default()
+        };
+        assert_eq!(movie_ids(&client, query).await, [3, 7, 9]);
+        assert_eq!(
+            server.targets(),
+            ["/api/movies?radarrid%5B%5D=3&radarrid%5B%5D=7&radarrid%5B%5D=9"]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_the_total_with_each_page() {
+        let (_server, client) = library(3, true).await;
+        let pages: Vec<Page<Movie>> = client
+            .movie_pages(&ListQuery::default())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(pages.len(), 2);
+        assert!(pages.iter().all(|page| page.total == Some(3)));
+    }
+
+    #[tokio::test]
+    async fn requests_the_episodes_of_several_series_at_once() {
+        let server = TestServer::start(|_| {
+            Reply::json(
+                r#"{"data": [
+                    {"sonarrSeriesId": 1, "sonarrEpisodeId": 10, "title": "Pilot", "subtitles": []},
+                    {"sonarrSeriesId": 2, "sonarrEpisodeId": 20, "title": "Pilot", "subtitles": []}
+                ]}"#,
+            )
+        })
+        .await;
+        let client = BazarrClient::new(
+            ClientBuilder::new(reqwest::Client::new()).build(),
+            server.url.clone(),
+        );
+        let episodes = client.episodes(&[1, 2]).await.unwrap();
+        assert_eq!(episodes.len(), 2);
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].query("seriesid[]"), ["1", "2"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tells_timeouts_from_connection_failures() {
+        let server = TestServer::start(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            Reply::json(r#"{"data": {"bazarr_version": "1.5.1"}}"#)
+        })
+        .await;
+        let http = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let client = BazarrClient::new(ClientBuilder::new(http).build(), server.url.clone());
+        let err = client.status().await.unwrap_err();
+        assert!(err.is_response_timeout(), "{err}");
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api", listener.local_addr().unwrap());
+        drop(listener);
+        let client = BazarrClient::new(
+            ClientBuilder::new(reqwest::Client::new()).build(),
+            url.parse().unwrap(),
+        );
+        let err = client.status().await.unwrap_err();
+        assert!(matches!(err, Error::Connection(_)), "{err}");
+        assert!(!err.is_response_timeout(), "{err}");
+    }
+
+    #[test]
+    fn counts_selected_records() {
+        let query = ListQuery {
+            start: 2,
+            length: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(query.selected(10), 5);
+        assert_eq!(query.selected(4), 2);
+        assert_eq!(query.selected(1), 0);
+        assert_eq!(ListQuery::default().selected(10), 10);
+    }
+}
@@ -1,58 +1,287 @@
-use std::process::exit;
-
-use reqwest::Url;
-use reqwest_middleware::ClientWithMiddleware;
-use serde::Deserialize;
-
-#[derive(Debug, Deserialize)]
-struct BazarrStatusData {
-    pub bazarr_version: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct BazarrStatus {
-    pub data: BazarrStatusData,
-}
-
-pub async fn check_health(client: &ClientWithMiddleware, url: &Url) {
-    let mut url = url.clone();
-    url.path_segments_mut().unwrap().push("system/status");
-    let response = client.get(url).send().await;
-    match response {
-        Ok(res) => {
-            if res.status().is_success() {
-                let json: Result<BazarrStatus, reqwest::Error> = res.json().await;
-                match json {
-                    Ok(json) => {
-                        if json.data.bazarr_version.is_some() {
-                            println!("Bazarr API is healthy.");
-                        }
-                    }
-                    Err(_) => {
-                        eprintln!("Error while connecting to Bazarr");
-                        exit(1);
-                    }
-                }
-            } else if res.status() == reqwest::StatusCode::UNAUTHORIZED {
-                eprintln!(
-                    "Unauthorized request! 
-                    Please verify that the correct Bazarr API key has been set in the configuration file."
-                );
-                exit(1);
-            } else {
-                eprintln!(
-                    "Error while connecting to Bazarr. Response: {}",
-                    res.text().await.unwrap()
-                );
-                println!("Attempting to continue anyway...")
-            }
+use std::{fmt, fs, str::FromStr, time::Duration};
+
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, WWW_AUTHENTICATE},
+    Certificate, Client, ClientBuilder, Identity, Proxy, Response, StatusCode,
+};
+
+use crate::{
+    client::BazarrClient,
+    data_types::{app_config::AppConfig, request::ActionCommands},
+    error::Error,
+};
+
+/// Build the HTTP client with the headers, timeouts, proxy and TLS settings of the configuration
+pub fn build_client(config: &AppConfig) -> Result<Client, Error> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &config.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| Error::Config(format!("Invalid header name '{name}': {e}")))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|e| Error::Config(format!("Invalid value of header '{name}': {e}")))?;
+        headers.insert(name, value);
+    }
+    let mut api_key = HeaderValue::from_str(&config.api_key)
+        .map_err(|_| Error::Config("The API key contains invalid characters".to_string()))?;
+    api_key.set_sensitive(true);
+    headers.insert("X-API-KEY", api_key);
+
+    let mut builder = Client::builder().default_headers(headers);
+    if config.connect_timeout > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(config.connect_timeout));
+    }
+    if config.request_timeout > 0 {
+        builder = builder.timeout(Duration::from_secs(config.request_timeout));
+    }
+    if let Some(proxy) = &config.proxy {
+        let proxy = Proxy::all(proxy).map_err(|e| Error::Config(format!("Invalid proxy: {e}")))?;
+        builder = builder.proxy(proxy);
+    }
+
+    configure_tls(builder, config)?
+        .build()
+        .map_err(|e| Error::Config(format!("Failed to set up the HTTP client: {e}")))
+}
+
+/// Apply the TLS settings of the configuration to the client builder
+fn configure_tls(mut builder: ClientBuilder, config: &AppConfig) -> Result<ClientBuilder, Error> {
+    if let Some(path) = &config.ca_certificate {
+        let pem = fs::read(path).map_err(|e| {
+            Error::Config(format!(
+                "Failed to read CA certificate {}: {e}",
+                path.display()
+            ))
+        })?;
+        for certificate in Certificate::from_pem_bundle(&pem)
+            .map_err(|e| Error::Config(format!("Invalid CA certificate {}: {e}", path.display())))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if let (Some(cert_path), Some(key_path)) = (&config.client_certificate, &config.client_key) {
+        let mut pem = fs::read(cert_path).map_err(|e| {
+            Error::Config(format!(
+                "Failed to read client certificate {}: {e}",
+                cert_path.display()
+            ))
+        })?;
+        let key = fs::read(key_path).map_err(|e| {
+            Error::Config(format!(
+                "Failed to read client key {}: {e}",
+                key_path.display()
+            ))
+        })?;
+        pem.push(b'\n');
+        pem.extend(key);
+        let identity = Identity::from_pem(&pem).map_err(|e| {
+            Error::Config(format!(
+                "Invalid client certificate {} or key {}: {e}",
+                cert_path.display(),
+                key_path.display()
+            ))
+        })?;
+        builder = builder.identity(identity);
+    }
+
+    if config.insecure_skip_verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+/// Bazarr release number, ignoring pre-release suffixes such as `-beta.3`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BazarrVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl BazarrVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl FromStr for BazarrVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let release = s
+            .trim()
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default();
+        let mut parts = release.split('.').map(|part| part.parse::<u32>());
+        let mut next = || parts.next().unwrap_or(Ok(0));
+        match (next(), next(), next()) {
+            (Ok(major), Ok(minor), Ok(patch)) => Ok(Self::new(major, minor, patch)),
+            _ => Err(format!("Unrecognized Bazarr version '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for BazarrVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Oldest Bazarr release known to support each action.
+/// Actions missing from the table are supported by every release exposing the API used by `bb`.
+const MINIMUM_VERSIONS: &[(&str, BazarrVersion)] = &[
+    // reference, max offset, framerate and golden-section search options of sync,
+    // added with the sync options of the subtitle editor
+    ("sync with options", BazarrVersion::new(1, 4, 0)),
+];
+
+/// Name of the action in the table of minimum versions
+fn feature_name(action: &ActionCommands) -> String {
+    match action {
+        ActionCommands::Sync(options)
+            if options.reference.is_some()
+                || options.max_offset_seconds.is_some()
+                || options.no_fix_framerate
+                || options.gss =>
+        {
+            "sync with options".to_string()
         }
-        Err(_) => {
-            println!(
-                "Unable to establish connection to Bazarr. 
-                Please verify that the protocol, host, and port provided in the configuration file are correct."
-            );
-            exit(1);
+        action => action.to_string(),
+    }
+}
+
+/// Refuse to start an action the Bazarr server is too old for.
+/// Returns a warning when the version is unknown, or when the check is skipped.
+pub fn check_compatibility(
+    version: Option<&str>,
+    action: &ActionCommands,
+    skip_check: bool,
+) -> Result<Option<String>, Error> {
+    let feature = feature_name(action);
+    let Some(&(_, minimum)) = MINIMUM_VERSIONS.iter().find(|(name, _)| *name == feature) else {
+        return Ok(None);
+    };
+    let version = match version.map(str::parse::<BazarrVersion>) {
+        Some(Ok(version)) => version,
+        Some(Err(e)) => {
+            return Ok(Some(format!(
+                "{e}, unable to verify that it supports {feature} (requires {minimum} or newer)"
+            )));
         }
+        None => {
+            return Ok(Some(format!("Bazarr did not report its version, unable to verify that it supports {feature} (requires {minimum} or newer)")));
+        }
+    };
+    if version >= minimum {
+        return Ok(None);
+    }
+
+    let msg =
+        format!("Bazarr {version} does not support {feature}, which requires {minimum} or newer");
+    if skip_check {
+        Ok(Some(msg))
+    } else {
+        Err(Error::Unsupported(format!(
+            "{msg}. Upgrade Bazarr or pass --skip-version-check to try anyway"
+        )))
+    }
+}
+
+/// Turn an unsuccessful response into an error
+pub async fn error_for_status(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    if status == StatusCode::UNAUTHORIZED {
+        // Bazarr itself does not send WWW-Authenticate, a reverse proxy asks for credentials
+        return Err(Error::Auth {
+            reverse_proxy: response.headers().contains_key(WWW_AUTHENTICATE),
+        });
+    }
+    Err(Error::Status {
+        status,
+        body: response.text().await.unwrap_or_default(),
+    })
+}
+
+/// Verify that Bazarr is reachable and return the version it reports.
+/// Fails with `Error::Status` when Bazarr answers with an error, which callers may choose to ignore.
+pub async fn check_health(client: &BazarrClient) -> Result<Option<String>, Error> {
+    Ok(client.status().await?.bazarr_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::request::SyncOptions;
+
+    fn sync(gss: bool) -> ActionCommands {
+        ActionCommands::Sync(SyncOptions {
+            reference: None,
+            max_offset_seconds: None,
+            no_fix_framerate: false,
+            gss,
+        })
+    }
+
+    #[test]
+    fn parses_versions() {
+        let parse = |s: &str| s.parse::<BazarrVersion>();
+        assert_eq!(parse("1.4.3"), Ok(BazarrVersion::new(1, 4, 3)));
+        assert_eq!(parse("v1.4.3"), Ok(BazarrVersion::new(1, 4, 3)));
+        assert_eq!(parse(" 1.5.0-beta.12 "), Ok(BazarrVersion::new(1, 5, 0)));
+        assert_eq!(parse("1.4.3+build"), Ok(BazarrVersion::new(1, 4, 3)));
+        assert_eq!(parse("1.4"), Ok(BazarrVersion::new(1, 4, 0)));
+        assert_eq!(parse("2"), Ok(BazarrVersion::new(2, 0, 0)));
+        assert!(parse("").is_err());
+        assert!(parse("unknown").is_err());
+        assert!(parse("1.x.0").is_err());
+    }
+
+    #[test]
+    fn orders_versions() {
+        assert!(BazarrVersion::new(1, 4, 0) > BazarrVersion::new(1, 3, 10));
+        assert!(BazarrVersion::new(2, 0, 0) > BazarrVersion::new(1, 99, 99));
+        assert_eq!(BazarrVersion::new(1, 4, 0).to_string(), "1.4.0");
+    }
+
+    #[test]
+    fn refuses_actions_known_to_be_unsupported() {
+        assert!(matches!(
+            check_compatibility(Some("1.3.1"), &sync(true), false),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(check_compatibility(Some("1.3.1"), &sync(true), true)
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            check_compatibility(Some("1.4.0"), &sync(true), false).unwrap(),
+            None
+        );
+        assert_eq!(
+            check_compatibility(Some("1.3.1"), &sync(false), false).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn warns_when_the_version_is_unknown() {
+        assert!(check_compatibility(None, &sync(true), false)
+            .unwrap()
+            .is_some());
+        assert!(check_compatibility(Some("nightly"), &sync(true), false)
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            check_compatibility(None, &ActionCommands::OCRFixes, false).unwrap(),
+            None
+        );
     }
 }
@@ -0,0 +1,296 @@
+use std::{io::IsTerminal, sync::Mutex};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use bazarr_bulk::{
+    data_types::{request::MediaType, response::Subtitle},
+    error::Error,
+    progress::{Event, Reporter},
+};
+
+/// Shows the progress of runs with progress bars, or line by line without a TTY
+pub struct ConsoleReporter {
+    is_tty: bool,
+    multi: MultiProgress,
+    /// Movies or TV shows
+    main: ProgressBar,
+    /// Episodes of the current TV show
+    episodes: ProgressBar,
+    run: Mutex<Run>,
+}
+
+/// Action and media type of the run being shown
+struct Run {
+    media_type: MediaType,
+    action: String,
+}
+
+impl ConsoleReporter {
+    pub fn new() -> Self {
+        let is_tty = std::io::stdout().is_terminal();
+        let new_bar = || {
+            if is_tty {
+                ProgressBar::new(0)
+            } else {
+                // For non-TTY environments, hide the progress bar
+                ProgressBar::hidden()
+            }
+        };
+        Self {
+            is_tty,
+            multi: MultiProgress::new(),
+            main: new_bar(),
+            episodes: new_bar(),
+            run: Mutex::new(Run {
+                media_type: MediaType::Movie,
+                action: String::new(),
+            }),
+        }
+    }
+
+    /// set message on progress bar or print to stdout based on TTY
+    fn log_info(&self, pb: &ProgressBar, msg: impl Into<String>) {
+        let message = msg.into();
+        if self.is_tty {
+            pb.set_message(message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// set error message on progress bar or print to stderr based on TTY
+    fn log_error(&self, pb: &ProgressBar, msg: impl Into<String>) {
+        let message = msg.into();
+        if self.is_tty {
+            pb.set_message(message);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// finish progress bar with message or print to stdout based on TTY
+    fn finish(&self, pb: &ProgressBar, msg: impl Into<String>) {
+        let message = msg.into();
+        if self.is_tty {
+            pb.finish_with_message(message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    fn set_style(&self, pb: &ProgressBar, template: &str) {
+        if self.is_tty {
+            pb.set_style(
+                ProgressStyle::with_template(template)
+                    .unwrap()
+                    .progress_chars("##-"),
+            );
+        }
+    }
+
+    /// Bar and description of the subtitles of the current movie or episode
+    fn subtitle_bar(&self, media_type: MediaType) -> (&ProgressBar, &'static str) {
+        match media_type {
+            MediaType::Movie => (&self.main, "movie"),
+            MediaType::TVShow => (&self.episodes, "episode"),
+        }
+    }
+
+    fn start(&self, media_type: MediaType) {
+        match media_type {
+            MediaType::Movie => self.set_style(
+                &self.main,
+                "[{bar:60.green/yellow}] {pos:>7}/{len:7} Movies {prefix}\n{msg}",
+            ),
+            MediaType::TVShow => {
+                self.multi.add(self.main.clone());
+                self.multi.insert_after(&self.main, self.episodes.clone());
+                self.set_style(
+                    &self.main,
+                    "[{bar:60.green/yellow}] {pos:>7}/{len:7} TV Shows {prefix}\n{msg}",
+                );
+                self.set_style(
+                    &self.episodes,
+                    "[{bar:60.cyan/blue}] {pos:>7}/{len:7} Episodes\n{msg}",
+                );
+            }
+        }
+    }
+
+    fn report_subtitle(
+        &self,
+        run: &Run,
+        title: &str,
+        subtitle: &Subtitle,
+        result: Option<Result<(), &Error>>,
+    ) {
+        let (pb, kind) = self.subtitle_bar(run.media_type);
+        let language = &subtitle.audio_language_item.name;
+        let action = &run.action;
+        match result {
+            None => self.log_info(
+                pb,
+                format!("Performing action {action} on {language} subtitle of {kind} {title}"),
+            ),
+            Some(Ok(())) => self.log_info(
+                pb,
+                format!(
+                    "Successfully performed action {action} on {language} subtitle of {kind} {title}"
+                ),
+            ),
+            Some(Err(error @ Error::Connection(err))) if !error.is_response_timeout() => {
+                self.log_error(&self.main, format!("Error connecting to Bazarr: {err}"))
+            }
+            Some(Err(err)) => self.log_error(
+                pb,
+                format!(
+                    "Error performing action {action} on {language} subtitle of {kind} {title}: {err}"
+                ),
+            ),
+        }
+    }
+}
+
+impl Reporter for ConsoleReporter {
+    fn report(&self, event: Event) {
+        let mut run = self.run.lock().unwrap();
+        let (items, item) = match run.media_type {
+            MediaType::Movie => ("movies", "movie"),
+            MediaType::TVShow => ("tv shows", "tv show"),
+        };
+        match event {
+            Event::Started { media_type, action } => {
+                *run = Run {
+                    media_type,
+                    action: action.to_string(),
+                };
+                self.start(media_type);
+            }
+            Event::Selected { count } => {
+                self.main.set_length(count);
+                if !self.is_tty && count > 0 {
+                    let kind = match run.media_type {
+                        MediaType::Movie => "movies",
+                        MediaType::TVShow => "TV shows",
+                    };
+                    println!("Processing {count} {kind}...");
+                }
+            }
+            Event::Listed { count } => self.main.inc_length(count),
+            Event::Skipped { count } => self.main.inc(count),
+            Event::AlreadyProcessed { count } => match run.media_type {
+                MediaType::Movie if count > 0 => {
+                    self.log_info(
+                        &self.main,
+                        format!("Skipped {count} already processed movies..."),
+                    );
+                    self.main.inc(count);
+                }
+                MediaType::Movie => {}
+                MediaType::TVShow if count > 0 => self.log_info(
+                    &self.main,
+                    format!("Skipped {count} already processed episodes..."),
+                ),
+                MediaType::TVShow => self.log_info(&self.main, "No previously processed episodes"),
+            },
+            Event::Resuming { title } => println!("Resuming from {item} {title}"),
+            Event::ResumeNotFound => {
+                println!("Last completed {item} not found, starting from the beginning");
+                self.main.set_position(0);
+                self.main.set_length(0);
+            }
+            Event::ItemStarted { title, .. } => match run.media_type {
+                MediaType::Movie if !self.is_tty => println!(
+                    "Processing movie {}/{}",
+                    self.main.position() + 1,
+                    self.main.length().unwrap_or_default()
+                ),
+                MediaType::Movie => {}
+                MediaType::TVShow => {
+                    let msg = format!("Processing tv show {title}");
+                    if self.is_tty {
+                        self.main.set_message(msg);
+                    } else {
+                        println!(
+                            "TV Show {}/{}: {}",
+                            self.main.position() + 1,
+                            self.main.length().unwrap_or_default(),
+                            msg
+                        );
+                    }
+                }
+            },
+            Event::ItemFinished { title, .. } => {
+                self.main.inc(1);
+                if run.media_type == MediaType::TVShow {
+                    self.log_info(&self.main, format!("Finished processing tv show {title}"));
+                }
+            }
+            Event::Episodes { count } => {
+                self.episodes.set_position(0);
+                self.episodes.set_length(count);
+                if count == 0 {
+                    self.finish(&self.episodes, "No episodes found");
+                } else if !self.is_tty {
+                    println!("  Processing {count} episodes...");
+                }
+            }
+            Event::EpisodeStarted { .. } => {
+                if !self.is_tty {
+                    println!(
+                        "    Episode {}/{}",
+                        self.episodes.position() + 1,
+                        self.episodes.length().unwrap_or_default()
+                    );
+                }
+            }
+            Event::EpisodeFinished { .. } => self.episodes.inc(1),
+            Event::SubtitleSkipped { title, subtitle } => {
+                let (pb, kind) = self.subtitle_bar(run.media_type);
+                self.log_info(
+                    pb,
+                    format!(
+                        "Skipping already processed {} subtitle of {kind} {title}",
+                        subtitle.audio_language_item.name
+                    ),
+                );
+            }
+            Event::Performing { title, subtitle } => {
+                self.report_subtitle(&run, title, subtitle, None)
+            }
+            Event::Performed {
+                title,
+                subtitle,
+                result,
+            } => self.report_subtitle(&run, title, subtitle, Some(result)),
+            Event::RateChanged { rate, latency } => {
+                let mut msg = format!("rate {rate}");
+                if let Some(latency) = latency {
+                    msg = format!("{msg}, latency {:.1}s", latency.as_secs_f64());
+                }
+                if self.is_tty {
+                    self.main.set_prefix(format!("| {msg}"));
+                } else {
+                    println!("Adaptive throttling: {msg}");
+                }
+            }
+            Event::NothingFound => self.finish(&self.main, format!("No {items} found")),
+            Event::Finished { stats, interrupted } => {
+                let action = &run.action;
+                if interrupted {
+                    self.finish(
+                        &self.main,
+                        format!("Stopped performing action {action} on {items}"),
+                    );
+                    println!("Partial summary: {}", stats.summary(true));
+                } else {
+                    self.finish(
+                        &self.main,
+                        format!("Finished performing action {action} on all {items}"),
+                    );
+                    println!("Summary: {}", stats.summary(false));
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,16 @@
-use std::fmt;
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
 
-use config::{Config, ConfigError, File, FileFormat};
+use config::{Config, ConfigError, File, FileFormat, FileSourceFile, Map, Value};
 use reqwest::Url;
 use serde::Deserialize;
 
-fn mask_credentials(url: &Url) -> Url {
+use crate::error::Error;
+
+pub fn mask_credentials(url: &Url) -> Url {
     let mut masked = url.clone();
     if !url.username().is_empty() {
         masked.set_username("*****").unwrap();
@@ -34,44 +40,446 @@ impl fmt::Display for Protocol {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AppConfig {
+    /// Name identifying this Bazarr instance in the database
+    pub name: Option<String>,
+    /// Full Bazarr URL, e.g. `https://media.example.lan/bazarr`.
+    /// Takes precedence over protocol, host, port and baseUrl.
+    pub url: Option<String>,
     pub protocol: Protocol,
     pub host: String,
     pub port: Option<String>,
     pub base_url: String,
     pub api_key: String,
+    /// Username for a basic-auth reverse proxy in front of Bazarr.
+    /// Takes precedence over a username embedded in the url.
+    pub username: Option<String>,
+    /// Password for a basic-auth reverse proxy in front of Bazarr
+    pub password: Option<String>,
+    /// PEM file with additional CA certificates to trust, e.g. a private CA
+    pub ca_certificate: Option<PathBuf>,
+    /// PEM file with the client certificate for mutual TLS
+    pub client_certificate: Option<PathBuf>,
+    /// PEM file with the private key of the client certificate
+    pub client_key: Option<PathBuf>,
+    /// Accept invalid and self-signed certificates, only meant for lab setups
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    /// Seconds to wait for a connection to Bazarr, 0 to wait indefinitely
+    pub connect_timeout: u64,
+    /// Seconds to wait for a response from Bazarr, 0 to wait indefinitely
+    pub request_timeout: u64,
+    /// HTTP, HTTPS or SOCKS5 proxy, e.g. `socks5://127.0.0.1:1080`
+    pub proxy: Option<String>,
+    /// Additional headers sent with every request, e.g. for authenticating proxies
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
 }
 
-impl AppConfig {
-    pub fn new(config_path: &str) -> Result<Self, ConfigError> {
-        let config = Config::builder()
-            .add_source(File::new(config_path, FileFormat::Json))
+/// Environment variables layered over the configuration file
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("BB_URL", "url"),
+    ("BB_HOST", "host"),
+    ("BB_PORT", "port"),
+    ("BB_PROTOCOL", "protocol"),
+    ("BB_BASE_URL", "baseUrl"),
+    ("BB_API_KEY", "apiKey"),
+    ("BB_USERNAME", "username"),
+    ("BB_PASSWORD", "password"),
+];
+
+/// Detect the configuration file format from its extension, defaulting to JSON
+fn file_format(config_path: &Path) -> Result<FileFormat, ConfigError> {
+    if !config_path.is_file() {
+        return Err(ConfigError::Message(format!(
+            "Configuration file {} not found",
+            config_path.display()
+        )));
+    }
+
+    let extension = config_path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        None | Some("json") => Ok(FileFormat::Json),
+        Some("toml") => Ok(FileFormat::Toml),
+        Some("yaml" | "yml") => Ok(FileFormat::Yaml),
+        Some(other) => Err(ConfigError::Message(format!(
+            "Unsupported configuration file extension '.{other}', expected .json, .toml, .yaml or .yml"
+        ))),
+    }
+}
+
+/// Rephrase a configuration error so it names the file and the offending key
+fn describe_error(source: &str, err: ConfigError) -> ConfigError {
+    let message = match err {
+        ConfigError::FileParse { cause, .. } => format!("Failed to parse {source}: {cause}"),
+        ConfigError::Type {
+            unexpected,
+            expected,
+            key: Some(key),
+            ..
+        } => format!(
+            "Invalid value for '{key}' in {source}: expected {expected}, found {unexpected}"
+        ),
+        ConfigError::At {
+            error,
+            key: Some(key),
+            ..
+        } => format!("Invalid value for '{key}' in {source}: {error}"),
+        ConfigError::NotFound(key) => format!("Missing '{key}' in {source}"),
+        ConfigError::Message(message) => match message.strip_prefix("missing field ") {
+            Some(field) => format!("Missing {} in {source}", field.replace('`', "'")),
+            None => message,
+        },
+        other => format!("Invalid configuration in {source}: {other}"),
+    };
+    ConfigError::Message(message)
+}
+
+/// Read the API key from the file given by BB_API_KEY_FILE (e.g. a Docker secret)
+fn api_key_from_file() -> Result<Option<String>, ConfigError> {
+    let Ok(path) = env::var("BB_API_KEY_FILE") else {
+        return Ok(None);
+    };
+    fs::read_to_string(&path)
+        .map(|key| Some(key.trim().to_string()))
+        .map_err(|e| ConfigError::Message(format!("Failed to read BB_API_KEY_FILE {path}: {e}")))
+}
+
+/// Table of named Bazarr instances in the configuration file
+const INSTANCES_KEY: &str = "instances";
+
+/// Settings of a named instance, layered over the top-level settings
+type InstanceTable = Map<String, Value>;
+
+/// Configuration file and environment variables, before an instance is selected
+struct Sources {
+    config: Config,
+    /// Description of where the configuration comes from, for error messages
+    origin: String,
+}
+
+impl Sources {
+    fn load(config_path: Option<&Path>) -> Result<Self, ConfigError> {
+        let (file, origin) = match config_path {
+            Some(config_path) => (
+                Some(File::from(config_path).format(file_format(config_path)?)),
+                config_path.display().to_string(),
+            ),
+            None if env::var_os("BB_API_KEY").is_none()
+                && env::var_os("BB_API_KEY_FILE").is_none() =>
+            {
+                return Err(ConfigError::Message(
+                    "No configuration provided. Pass a configuration file with --config, \
+                     or set BB_API_KEY (or BB_API_KEY_FILE) and the other BB_* environment variables"
+                        .to_string(),
+                ));
+            }
+            None => (None, "BB_* environment variables".to_string()),
+        };
+
+        let config = Self::build(file).map_err(|e| describe_error(&origin, e))?;
+        Ok(Self { config, origin })
+    }
+
+    fn build(file: Option<File<FileSourceFile, FileFormat>>) -> Result<Config, ConfigError> {
+        let mut builder = Config::builder()
             .set_default("host", "0.0.0.0")?
             .set_default("protocol", "http")?
             .set_default("baseUrl", "")?
-            .build()?;
+            .set_default("connectTimeout", 10)?
+            .set_default("requestTimeout", 600)?;
+        if let Some(file) = file {
+            builder = builder.add_source(file);
+        }
+        builder.build()
+    }
+
+    /// Named instances defined in the configuration file, sorted by name
+    fn instances(&self) -> Result<Vec<(String, InstanceTable)>, ConfigError> {
+        let instances = match self.config.get_table(INSTANCES_KEY) {
+            Ok(instances) => instances,
+            Err(ConfigError::NotFound(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(describe_error(&self.origin, e)),
+        };
+        let mut instances = instances
+            .into_iter()
+            .map(|(name, table)| {
+                let table = table.into_table().map_err(|_| {
+                    ConfigError::Message(format!(
+                        "Invalid value for '{INSTANCES_KEY}.{name}' in {}: expected a table of settings",
+                        self.origin
+                    ))
+                })?;
+                Ok((name, table))
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+        instances.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(instances)
+    }
+
+    /// Layer the instance settings, and the BB_* environment variables if `env` is set,
+    /// over the top-level settings
+    fn resolve(
+        &self,
+        instance: Option<(&str, &InstanceTable)>,
+        env: bool,
+    ) -> Result<AppConfig, ConfigError> {
+        let origin = match instance {
+            Some((name, _)) => format!("instance '{name}' of {}", self.origin),
+            None => self.origin.clone(),
+        };
+        let config = Self::layer(self.config.clone(), instance, env)
+            .map_err(|e| describe_error(&origin, e))?;
+        config.bazarr_url().map_err(|(key, e)| {
+            ConfigError::Message(format!("Invalid value for '{key}' in {origin}: {e}"))
+        })?;
+        if config.client_certificate.is_some() != config.client_key.is_some() {
+            return Err(ConfigError::Message(format!(
+                "clientCertificate and clientKey must be set together in {origin}"
+            )));
+        }
+        Ok(config)
+    }
+
+    fn layer(
+        config: Config,
+        instance: Option<(&str, &InstanceTable)>,
+        env: bool,
+    ) -> Result<AppConfig, ConfigError> {
+        let mut builder = Config::builder().add_source(config);
+        if let Some((name, table)) = instance {
+            builder = builder.set_override("name", name)?;
+            for (key, value) in table {
+                builder = builder.set_override(key.as_str(), value.clone())?;
+            }
+        }
+
+        if env {
+            builder = builder.set_override_option("apiKey", api_key_from_file()?)?;
+            for (var, key) in ENV_OVERRIDES {
+                builder = builder.set_override_option(*key, env::var(var).ok())?;
+            }
+        }
+
+        builder.build()?.try_deserialize()
+    }
+}
+
+impl AppConfig {
+    /// Load the configuration file (if any) and layer the BB_* environment variables over it.
+    /// When the file defines named instances, the given instance is selected,
+    /// which may be omitted if there is only one.
+    pub fn new(config_path: Option<&Path>, instance: Option<&str>) -> Result<Self, ConfigError> {
+        let sources = Sources::load(config_path)?;
+        let instances = sources.instances()?;
+        let names = || {
+            instances
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
 
-        config.try_deserialize()
+        match (instance, instances.as_slice()) {
+            (None, []) => sources.resolve(None, true),
+            (Some(name), []) => Err(ConfigError::Message(format!(
+                "Instance '{name}' not found, {} does not define any instances",
+                sources.origin
+            ))),
+            (None, [(name, table)]) => sources.resolve(Some((name, table)), true),
+            (None, _) => Err(ConfigError::Message(format!(
+                "{} defines several instances ({}), select one with --instance or use --all-instances",
+                sources.origin,
+                names()
+            ))),
+            (Some(name), _) => match instances.iter().find(|(n, _)| n == name) {
+                Some((name, table)) => sources.resolve(Some((name, table)), true),
+                None => Err(ConfigError::Message(format!(
+                    "Instance '{name}' not found in {}, available instances: {}",
+                    sources.origin,
+                    names()
+                ))),
+            },
+        }
     }
 
-    pub fn construct_url(&self) -> Url {
-        let mut bazarr_url = format!("{}://{}", self.protocol, self.host);
+    /// Load the configuration of every named instance,
+    /// or the single configuration if the file does not define any.
+    /// The BB_* environment variables only apply when there is a single instance,
+    /// so that they do not override the settings of every instance.
+    pub fn all(config_path: Option<&Path>) -> Result<Vec<Self>, ConfigError> {
+        let sources = Sources::load(config_path)?;
+        let instances = sources.instances()?;
+        if instances.is_empty() {
+            return Ok(vec![sources.resolve(None, true)?]);
+        }
+        let env = instances.len() == 1;
+        instances
+            .iter()
+            .map(|(name, table)| sources.resolve(Some((name, table)), env))
+            .collect()
+    }
 
-        if let Some(port) = &self.port {
-            bazarr_url = format!("{}:{}", bazarr_url, port);
+    /// Bazarr URL without the `/api` path, along with the offending key when it is invalid
+    fn bazarr_url(&self) -> Result<Url, (&'static str, String)> {
+        let (key, raw) = match &self.url {
+            Some(url) => ("url", url.trim().to_string()),
+            None => {
+                let mut url = format!("{}://{}", self.protocol, self.host);
+                if let Some(port) = &self.port {
+                    if port.parse::<u16>().is_err() {
+                        return Err(("port", format!("'{port}' is not a valid port number")));
+                    }
+                    url = format!("{}:{}", url, port);
+                }
+                (
+                    "host",
+                    format!("{}/{}", url, self.base_url.trim_matches('/')),
+                )
+            }
+        };
+
+        let mut url =
+            Url::parse(&raw).map_err(|e| (key, format!("'{raw}' is not a valid URL ({e})")))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err((key, format!("'{raw}' must use http or https")));
+        }
+        if url.host_str().is_none_or(str::is_empty) {
+            return Err((key, format!("'{raw}' has no host")));
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            return Err((key, format!("'{raw}' must not contain a query or fragment")));
+        }
+
+        // credentials embedded in the URL are sent as basic auth by reqwest
+        if let Some(username) = &self.username {
+            url.set_username(username)
+                .map_err(|_| ("username", "cannot be used with this URL".to_string()))?;
+        }
+        if let Some(password) = &self.password {
+            if url.username().is_empty() {
+                return Err(("password", "requires a username".to_string()));
+            }
+            url.set_password(Some(password))
+                .map_err(|_| ("password", "cannot be used with this URL".to_string()))?;
         }
+        Ok(url)
+    }
+
+    /// URL of the Bazarr API, e.g. `http://0.0.0.0:6767/bazarr/api`
+    pub fn construct_url(&self) -> Result<Url, Error> {
+        let mut url = self
+            .bazarr_url()
+            .map_err(|(key, e)| Error::Config(format!("Invalid value for '{key}': {e}")))?;
 
-        // clean the base_url by removing leading and trailing slashes
-        let clean_base_url = self.base_url.trim_matches('/');
+        // drop empty segments so a blank or slash-wrapped base URL doesn't produce `//api`
+        let mut segments: Vec<String> = url
+            .path_segments()
+            .map(|segments| {
+                segments
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        // a configured url may already point at the API
+        if self.url.is_some() && segments.last().is_some_and(|s| s == "api") {
+            segments.pop();
+        }
 
-        let mut url = Url::parse(&bazarr_url).unwrap();
         url.path_segments_mut()
-            .unwrap()
-            .push(clean_base_url)
+            .map_err(|()| Error::Config("The Bazarr URL must use http or https".to_string()))?
+            .clear()
+            .extend(&segments)
             .push("api");
 
-        let masked_url = mask_credentials(&url);
-        println!("Bazarr API URL: {}", masked_url);
+        Ok(url)
+    }
 
-        url
+    /// Identifier of the Bazarr instance that processing records are scoped to.
+    /// Uses the configured name, or the Bazarr URL without credentials.
+    pub fn instance_id(&self) -> Result<String, Error> {
+        if let Some(name) = &self.name {
+            return Ok(name.clone());
+        }
+
+        let url = self.construct_url()?;
+        let segments: Vec<&str> = url
+            .path_segments()
+            .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        let base_path = segments[..segments.len().saturating_sub(1)].join("/");
+        let mut instance = format!("{}://{}", url.scheme(), url.host_str().unwrap_or_default());
+        if let Some(port) = url.port() {
+            instance = format!("{}:{}", instance, port);
+        }
+        if !base_path.is_empty() {
+            instance = format!("{}/{}", instance, base_path);
+        }
+        Ok(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Configuration built without `AppConfig::new`, as library users may
+    fn parse(settings: &str) -> AppConfig {
+        Config::builder()
+            .add_source(File::from_str(settings, FileFormat::Json))
+            .set_default("protocol", "http")
+            .unwrap()
+            .set_default("host", "localhost")
+            .unwrap()
+            .set_default("baseUrl", "")
+            .unwrap()
+            .set_default("apiKey", "key")
+            .unwrap()
+            .set_default("connectTimeout", 10)
+            .unwrap()
+            .set_default("requestTimeout", 600)
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn constructs_the_api_url() {
+        let config = parse(r#"{"port": "6767", "baseUrl": "/bazarr/"}"#);
+        assert_eq!(
+            config.construct_url().unwrap().as_str(),
+            "http://localhost:6767/bazarr/api"
+        );
+        assert_eq!(
+            config.instance_id().unwrap(),
+            "http://localhost:6767/bazarr"
+        );
+        let config = parse(r#"{"url": "https://media.example.lan/bazarr/api/"}"#);
+        assert_eq!(
+            config.construct_url().unwrap().as_str(),
+            "https://media.example.lan/bazarr/api"
+        );
+    }
+
+    #[test]
+    fn reports_invalid_urls() {
+        for settings in [
+            r#"{"port": "http"}"#,
+            r#"{"url": "not a url"}"#,
+            r#"{"url": "ftp://localhost"}"#,
+        ] {
+            let config = parse(settings);
+            assert!(
+                matches!(config.construct_url(), Err(Error::Config(_))),
+                "{settings}"
+            );
+            assert!(config.instance_id().is_err(), "{settings}");
+        }
+        let named = parse(r#"{"name": "hd", "port": "http"}"#);
+        assert_eq!(named.instance_id().unwrap(), "hd");
     }
 }
@@ -1,12 +1,14 @@
-use clap::ValueEnum;
+use clap::{Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use super::response::Subtitle;
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
 pub enum MediaType {
+    #[serde(rename = "movie")]
     Movie,
+    #[serde(rename = "tv-show")]
     TVShow,
 }
 
@@ -22,6 +24,15 @@ impl FromStr for MediaType {
     }
 }
 
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MediaType::Movie => write!(f, "movie"),
+            MediaType::TVShow => write!(f, "tv-show"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ActionPayload {
     pub id: u32,
@@ -42,16 +53,66 @@ pub struct ActionPayload {
 }
 
 impl ActionPayload {
-    pub fn new(id: u32, media_type: &str, subtitle: &Subtitle) -> Self {
-        ActionPayload {
+    /// Payload of an action on the subtitle, None if it has no file or language code
+    pub fn new(id: u32, media_type: &str, subtitle: &Subtitle) -> Option<Self> {
+        Some(ActionPayload {
             id,
             media_type: String::from(media_type),
-            language: subtitle.audio_language_item.code2.clone().unwrap(),
-            path: subtitle.path.clone().unwrap(),
+            language: subtitle.audio_language_item.code2.clone()?,
+            path: subtitle.path.clone()?,
             reference: None,
             max_offset_seconds: None,
             no_fix_framerate: None,
             gss: None,
+        })
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ActionCommands {
+    /// Sync all
+    Sync(SyncOptions),
+    /// Perform OCR fixes
+    OCRFixes,
+    /// Perform common fixes
+    CommonFixes,
+    /// Remove hearing impaired tags from subtitles
+    RemoveHearingImpaired,
+    /// Remove style tags from subtitles
+    RemoveStyleTags,
+    /// Fix uppercase subtitles
+    FixUppercase,
+    /// Reverse RTL directioned subtitles
+    ReverseRTL,
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for ActionCommands {
+    fn to_string(&self) -> String {
+        match self {
+            ActionCommands::Sync(_) => "sync".to_string(),
+            ActionCommands::OCRFixes => "OCR_fixes".to_string(),
+            ActionCommands::CommonFixes => "common".to_string(),
+            ActionCommands::RemoveHearingImpaired => "remove_HI".to_string(),
+            ActionCommands::RemoveStyleTags => "remove_tags".to_string(),
+            ActionCommands::FixUppercase => "fix_uppercase".to_string(),
+            ActionCommands::ReverseRTL => "reverse_rtl".to_string(),
         }
     }
 }
+
+#[derive(clap::Args, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SyncOptions {
+    /// Reference for sync from video file track number (a:0), subtitle (s:0), or some subtitles file path
+    #[arg(short)]
+    pub reference: Option<String>,
+    /// Seconds of offset allowed when syncing [default: null]
+    #[arg(short, value_name = "MAX OFFSET")]
+    pub max_offset_seconds: Option<u32>,
+    /// Do not attempt to fix framerate [default: false]
+    #[arg(short, default_value_t = false)]
+    pub no_fix_framerate: bool,
+    /// Use Golden-Section search [default: false]
+    #[arg(short, default_value_t = false)]
+    pub gss: bool,
+}
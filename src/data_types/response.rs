@@ -1,16 +1,48 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PaginatedResponse<T> {
     pub data: Vec<T>,
+    /// Number of records matching the filters, regardless of `start` and `length`
+    #[serde(default)]
+    pub total: Option<u32>,
+}
+
+/// Response of `/api/system/status`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SystemStatusResponse {
+    pub data: SystemStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SystemStatus {
+    pub bazarr_version: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Movie {
-    pub subtitles: Vec<Subtitle>,
     #[serde(rename = "radarrId")]
     pub radarr_id: u32,
     pub title: String,
+    pub year: Option<String>,
+    #[serde(rename = "imdbId")]
+    pub imdb_id: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub monitored: bool,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub tags: Vec<String>,
+    /// Path of the movie file
+    pub path: Option<String>,
+    #[serde(rename = "sceneName")]
+    pub scene_name: Option<String>,
+    /// Languages profile of the movie
+    #[serde(rename = "profileId")]
+    pub profile_id: Option<u32>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub audio_language: Vec<AudioLanguageItem>,
+    pub subtitles: Vec<Subtitle>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub missing_subtitles: Vec<MissingSubtitle>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -18,27 +50,76 @@ pub struct TVShow {
     #[serde(rename = "sonarrSeriesId")]
     pub sonarr_series_id: u32,
     pub title: String,
+    pub year: Option<String>,
+    #[serde(rename = "imdbId")]
+    pub imdb_id: Option<String>,
+    #[serde(rename = "tvdbId")]
+    pub tvdb_id: Option<u32>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub monitored: bool,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub tags: Vec<String>,
+    /// Directory of the TV show
+    pub path: Option<String>,
+    /// Languages profile of the TV show
+    #[serde(rename = "profileId")]
+    pub profile_id: Option<u32>,
+    /// Standard, daily or anime
+    #[serde(rename = "seriesType")]
+    pub series_type: Option<String>,
+    #[serde(rename = "episodeFileCount")]
+    pub episode_file_count: Option<u32>,
+    /// Number of episodes with missing subtitles
+    #[serde(rename = "episodeMissingCount")]
+    pub episode_missing_count: Option<u32>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub audio_language: Vec<AudioLanguageItem>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Episode {
+    #[serde(rename = "sonarrSeriesId")]
+    pub sonarr_series_id: u32,
     #[serde(rename = "sonarrEpisodeId")]
     pub sonarr_episode_id: u32,
-    pub subtitles: Vec<Subtitle>,
     pub title: String,
+    /// Season number, 0 for specials
+    pub season: Option<u32>,
+    /// Episode number within the season
+    pub episode: Option<u32>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub monitored: bool,
+    /// Path of the episode file
+    pub path: Option<String>,
+    #[serde(rename = "sceneName")]
+    pub scene_name: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub audio_language: Vec<AudioLanguageItem>,
+    pub subtitles: Vec<Subtitle>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub missing_subtitles: Vec<MissingSubtitle>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AudioLanguageItem {
     pub name: String,
     pub code2: Option<String>,
+    pub code3: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Subtitle {
+    /// Path of the subtitle file, unset for subtitles embedded in the video file
     pub path: Option<String>,
     #[serde(flatten)]
     pub audio_language_item: AudioLanguageItem,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub forced: bool,
+    /// Hearing impaired
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub hi: bool,
+    /// Size of the subtitle file in bytes
+    pub file_size: Option<u64>,
 }
 
 impl Subtitle {
@@ -46,3 +127,59 @@ impl Subtitle {
         self.path.is_some() && self.audio_language_item.code2.is_some()
     }
 }
+
+/// Language of a subtitle that Bazarr is still looking for
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MissingSubtitle {
+    #[serde(flatten)]
+    pub audio_language_item: AudioLanguageItem,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub forced: bool,
+    /// Hearing impaired
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub hi: bool,
+}
+
+/// Decode `null` as the default value, as Bazarr leaves fields empty when it has not synced them yet
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(fields: &str) -> serde_json::Result<Episode> {
+        serde_json::from_str(&format!(
+            r#"{{"sonarrSeriesId": 1, "sonarrEpisodeId": 2, "title": "Pilot", "subtitles": []{fields}}}"#
+        ))
+    }
+
+    #[test]
+    fn decodes_missing_and_null_episode_numbers_alike() {
+        let numbered = episode(r#", "season": 1, "episode": 3"#).unwrap();
+        assert_eq!((numbered.season, numbered.episode), (Some(1), Some(3)));
+        let missing = episode("").unwrap();
+        assert_eq!((missing.season, missing.episode), (None, None));
+        let null = episode(r#", "season": null, "episode": null"#).unwrap();
+        assert_eq!((null.season, null.episode), (None, None));
+    }
+
+    #[test]
+    fn decodes_subtitles() {
+        let subtitle: Subtitle = serde_json::from_str(
+            r#"{"name": "English", "code2": "en", "code3": "eng", "path": "/movie.en.srt",
+                "forced": null, "hi": true, "file_size": 1024}"#,
+        )
+        .unwrap();
+        assert_eq!(subtitle.audio_language_item.code2.as_deref(), Some("en"));
+        assert!(!subtitle.forced);
+        assert!(subtitle.hi);
+        assert_eq!(subtitle.file_size, Some(1024));
+        assert!(subtitle.is_valid());
+    }
+}
@@ -1,50 +1,138 @@
+use clap::ValueEnum;
 use directories::ProjectDirs;
-use rusqlite::{params, Connection, Result};
-use std::{collections::HashSet, path::PathBuf, sync::Arc};
+use rusqlite::{
+    params, params_from_iter,
+    types::{Type, Value},
+    Connection, OptionalExtension, Result, Row,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt, path::PathBuf, str::FromStr, sync::Arc};
 use tokio::sync::Mutex;
 
-use crate::data_types::response::{Episode, Movie, Subtitle};
+use crate::data_types::{
+    request::MediaType,
+    response::{Episode, Movie, Subtitle},
+};
 
-fn get_db_path(custom_path: Option<PathBuf>) -> std::result::Result<PathBuf, String> {
+/// Maximum number of media IDs bound in a single `IN (...)` query
+const ID_CHUNK_SIZE: usize = 500;
+
+/// Schema migrations applied in order on top of the base tables.
+/// The number of applied migrations is tracked in `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    // record the action that processed the subtitle
+    "ALTER TABLE processed_movie_subtitles ADD COLUMN action TEXT;
+     ALTER TABLE processed_episode_subtitles ADD COLUMN action TEXT;",
+    // scope records to the Bazarr instance they were processed on
+    "CREATE TABLE processed_movie_subtitles_new (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        instance TEXT NOT NULL DEFAULT '',
+        radarr_id INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        language_code TEXT NOT NULL,
+        language_name TEXT NOT NULL,
+        path TEXT,
+        action TEXT,
+        processed_at INTEGER NOT NULL,
+        UNIQUE(instance, radarr_id, language_code)
+     );
+     INSERT INTO processed_movie_subtitles_new
+        (id, radarr_id, title, language_code, language_name, path, action, processed_at)
+        SELECT id, radarr_id, title, language_code, language_name, path, action, processed_at
+        FROM processed_movie_subtitles;
+     DROP TABLE processed_movie_subtitles;
+     ALTER TABLE processed_movie_subtitles_new RENAME TO processed_movie_subtitles;
+     CREATE INDEX idx_movie_radarr ON processed_movie_subtitles(radarr_id);
+     CREATE TABLE processed_episode_subtitles_new (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        instance TEXT NOT NULL DEFAULT '',
+        sonarr_episode_id INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        language_code TEXT NOT NULL,
+        language_name TEXT NOT NULL,
+        path TEXT,
+        action TEXT,
+        processed_at INTEGER NOT NULL,
+        UNIQUE(instance, sonarr_episode_id, language_code)
+     );
+     INSERT INTO processed_episode_subtitles_new
+        (id, sonarr_episode_id, title, language_code, language_name, path, action, processed_at)
+        SELECT id, sonarr_episode_id, title, language_code, language_name, path, action, processed_at
+        FROM processed_episode_subtitles;
+     DROP TABLE processed_episode_subtitles;
+     ALTER TABLE processed_episode_subtitles_new RENAME TO processed_episode_subtitles;
+     CREATE INDEX idx_episode_sonarr ON processed_episode_subtitles(sonarr_episode_id);",
+    // append-only log of every attempted action
+    "CREATE TABLE action_log (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        run_id TEXT NOT NULL,
+        instance TEXT NOT NULL,
+        action TEXT NOT NULL,
+        options TEXT,
+        media_type TEXT NOT NULL,
+        media_id INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        language_code TEXT,
+        subtitle_path TEXT,
+        result TEXT NOT NULL,
+        http_status INTEGER,
+        error TEXT,
+        duration_ms INTEGER NOT NULL,
+        performed_at INTEGER NOT NULL
+     );
+     CREATE INDEX idx_action_log_performed_at ON action_log(performed_at);
+     CREATE INDEX idx_action_log_run ON action_log(run_id);",
+    // checkpoints of runs, used to resume interrupted runs
+    "CREATE TABLE run_checkpoints (
+        run_id TEXT PRIMARY KEY,
+        instance TEXT NOT NULL,
+        media_type TEXT NOT NULL,
+        parameters TEXT NOT NULL,
+        status TEXT NOT NULL,
+        series_id INTEGER,
+        media_id INTEGER,
+        language_code TEXT,
+        started_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+     );",
+];
+
+/// Resolve the database file path, creating its directory
+pub fn get_db_path(custom_path: Option<PathBuf>) -> std::result::Result<PathBuf, String> {
+    let path = resolve_db_path(custom_path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create database directory: {}", e))?;
+    }
+    Ok(path)
+}
+
+/// Resolve the database file path without creating anything
+pub fn resolve_db_path(custom_path: Option<PathBuf>) -> std::result::Result<PathBuf, String> {
     // Priority: 1. CLI argument, 2. Environment variable, 3. Default user data directory
     if let Some(path) = custom_path {
-        // Ensure parent directory exists
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create database directory: {}", e))?;
-        }
         return Ok(path);
     }
 
     // Check BB_DATA_DIR environment variable
     if let Ok(data_dir) = std::env::var("BB_DATA_DIR") {
-        let db_path = PathBuf::from(data_dir).join("database.db");
-        if let Some(parent) = db_path.parent() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create database directory: {}", e))?;
-        }
-        return Ok(db_path);
+        return Ok(PathBuf::from(data_dir).join("database.db"));
     }
 
     // Fallback to default user data directory
     ProjectDirs::from("com", "mateoradman", "bazarr-bulk")
-        .and_then(|proj_dirs| {
-            let data_dir = proj_dirs.data_local_dir();
-            std::fs::create_dir_all(data_dir).ok()?;
-            Some(data_dir.join("database.db"))
-        })
+        .map(|proj_dirs| proj_dirs.data_local_dir().join("database.db"))
         .ok_or_else(|| "Failed to obtain a default database path".to_string())
 }
 
 pub async fn init_db(custom_path: Option<PathBuf>) -> Result<Arc<Mutex<Connection>>> {
     let db_path = get_db_path(custom_path).map_err(|e| rusqlite::Error::InvalidPath(e.into()))?;
 
-    println!("Using database at: {}", db_path.display());
-
     let conn = tokio::task::spawn_blocking(move || {
         let mut conn = Connection::open(db_path)?;
         conn.execute("PRAGMA foreign_keys = ON", [])?;
         create_tables(&mut conn)?;
+        migrate(&mut conn)?;
         Ok::<_, rusqlite::Error>(conn)
     })
     .await
@@ -115,35 +203,26 @@ fn create_tables(conn: &mut Connection) -> Result<()> {
     Ok(())
 }
 
-pub async fn is_movie_subtitle_processed(
-    conn: Arc<Mutex<Connection>>,
-    radarr_id: u32,
-    language_code: String,
-) -> Result<bool> {
-    tokio::task::spawn_blocking(move || {
-        let conn = conn.blocking_lock();
-        let mut stmt = conn.prepare(
-            "SELECT 1 FROM processed_movie_subtitles 
-             WHERE radarr_id = ?1 AND language_code = ?2",
-        )?;
-        stmt.exists(params![radarr_id, language_code])
-    })
-    .await
-    .map_err(|e| rusqlite::Error::InvalidPath(e.to_string().into()))?
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", idx + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
 }
 
-pub async fn is_episode_subtitle_processed(
-    conn: Arc<Mutex<Connection>>,
-    sonarr_episode_id: u32,
-    language_code: String,
-) -> Result<bool> {
+/// Run a blocking closure against the shared connection
+async fn with_conn<T, F>(conn: Arc<Mutex<Connection>>, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+{
     tokio::task::spawn_blocking(move || {
-        let conn = conn.blocking_lock();
-        let mut stmt = conn.prepare(
-            "SELECT 1 FROM processed_episode_subtitles 
-             WHERE sonarr_episode_id = ?1 AND language_code = ?2",
-        )?;
-        stmt.exists(params![sonarr_episode_id, language_code])
+        let mut conn = conn.blocking_lock();
+        f(&mut conn)
     })
     .await
     .map_err(|e| rusqlite::Error::InvalidPath(e.to_string().into()))?
@@ -151,9 +230,11 @@ pub async fn is_episode_subtitle_processed(
 
 pub async fn mark_episode_subtitle_processed(
     conn: Arc<Mutex<Connection>>,
+    instance: String,
     sonarr_episode_id: u32,
     title: String,
     subtitle: Subtitle,
+    action: String,
 ) -> Result<bool> {
     let Some(language_code) = subtitle.audio_language_item.code2 else {
         return Ok(false);
@@ -161,22 +242,21 @@ pub async fn mark_episode_subtitle_processed(
 
     tokio::task::spawn_blocking(move || {
         let conn = conn.blocking_lock();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        let now = now();
 
         let rows = conn.execute(
             "INSERT INTO processed_episode_subtitles 
-             (sonarr_episode_id, title, language_code, language_name, path, processed_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-             ON CONFLICT(sonarr_episode_id, language_code) DO NOTHING",
+             (instance, sonarr_episode_id, title, language_code, language_name, path, action, processed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT(instance, sonarr_episode_id, language_code) DO NOTHING",
             params![
+                instance,
                 sonarr_episode_id,
                 title,
                 language_code,
                 subtitle.audio_language_item.name,
                 subtitle.path,
+                action,
                 now
             ],
         )?;
@@ -189,9 +269,11 @@ pub async fn mark_episode_subtitle_processed(
 
 pub async fn mark_movie_subtitle_processed(
     conn: Arc<Mutex<Connection>>,
+    instance: String,
     radarr_id: u32,
     title: String,
     subtitle: Subtitle,
+    action: String,
 ) -> Result<bool> {
     let Some(language_code) = subtitle.audio_language_item.code2 else {
         return Ok(false);
@@ -199,22 +281,21 @@ pub async fn mark_movie_subtitle_processed(
 
     tokio::task::spawn_blocking(move || {
         let conn = conn.blocking_lock();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        let now = now();
 
         let rows = conn.execute(
             "INSERT INTO processed_movie_subtitles 
-             (radarr_id, title, language_code, language_name, path, processed_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-             ON CONFLICT(radarr_id, language_code) DO NOTHING",
+             (instance, radarr_id, title, language_code, language_name, path, action, processed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT(instance, radarr_id, language_code) DO NOTHING",
             params![
+                instance,
                 radarr_id,
                 title,
                 language_code,
                 subtitle.audio_language_item.name,
                 subtitle.path,
+                action,
                 now
             ],
         )?;
@@ -225,136 +306,1069 @@ pub async fn mark_movie_subtitle_processed(
     .map_err(|e| rusqlite::Error::InvalidPath(e.to_string().into()))?
 }
 
+/// (media ID, language code) keys of processed subtitles
+pub type ProcessedKeys = HashSet<(u32, String)>;
+
+/// Load the (media ID, language code) keys of processed subtitles for the given media IDs.
+/// IDs are queried in chunks to stay within SQLite's limit on bound parameters.
+pub async fn processed_subtitle_keys(
+    conn: Arc<Mutex<Connection>>,
+    media_type: MediaType,
+    instance: String,
+    ids: Vec<u32>,
+) -> Result<ProcessedKeys> {
+    if ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    with_conn(conn, move |conn| {
+        let (table, id_column) = processed_table(&media_type);
+        let mut keys = HashSet::new();
+        for chunk in ids.chunks(ID_CHUNK_SIZE) {
+            let placeholders = chunk.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT {id_column}, language_code FROM {table}
+                 WHERE instance = ? AND {id_column} IN ({placeholders})"
+            ))?;
+            let values = std::iter::once(Value::Text(instance.clone()))
+                .chain(chunk.iter().map(|id| Value::Integer(i64::from(*id))));
+            let rows = stmt.query_map(params_from_iter(values), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+            for row in rows {
+                keys.insert(row?);
+            }
+        }
+        Ok(keys)
+    })
+    .await
+}
+
+/// Returns true unless the media has been processed and all of its subtitles are in `processed`
+fn has_unprocessed_subtitles(
+    media_id: u32,
+    subtitles: &[Subtitle],
+    processed: &ProcessedKeys,
+    processed_ids: &HashSet<u32>,
+) -> bool {
+    if !processed_ids.contains(&media_id) {
+        return true;
+    }
+
+    subtitles
+        .iter()
+        .filter_map(|sub| sub.audio_language_item.code2.clone())
+        .any(|code| !processed.contains(&(media_id, code)))
+}
+
 pub async fn filter_unprocessed_movies(
     conn: Arc<Mutex<Connection>>,
+    instance: String,
     movies: Vec<Movie>,
-) -> Result<Vec<Movie>> {
+) -> Result<(Vec<Movie>, ProcessedKeys)> {
     if movies.is_empty() {
-        return Ok(vec![]);
+        return Ok((vec![], ProcessedKeys::new()));
     }
 
     let radarr_ids: Vec<u32> = movies.iter().map(|m| m.radarr_id).collect();
-    let conn_clone = conn.clone();
-
-    let processed_ids: HashSet<u32> = tokio::task::spawn_blocking(move || {
-        let conn = conn_clone.blocking_lock();
-        let placeholders = radarr_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-        let query = format!(
-            "SELECT DISTINCT radarr_id FROM processed_movie_subtitles 
-             WHERE radarr_id IN ({})",
-            placeholders
-        );
+    let processed = processed_subtitle_keys(conn, MediaType::Movie, instance, radarr_ids).await?;
+    let processed_ids: HashSet<u32> = processed.iter().map(|(id, _)| *id).collect();
+
+    let movies = movies
+        .into_iter()
+        .filter(|movie| {
+            has_unprocessed_subtitles(
+                movie.radarr_id,
+                &movie.subtitles,
+                &processed,
+                &processed_ids,
+            )
+        })
+        .collect();
+    Ok((movies, processed))
+}
+
+pub async fn filter_unprocessed_episodes(
+    conn: Arc<Mutex<Connection>>,
+    instance: String,
+    episodes: Vec<Episode>,
+) -> Result<(Vec<Episode>, ProcessedKeys)> {
+    if episodes.is_empty() {
+        return Ok((vec![], ProcessedKeys::new()));
+    }
+
+    let episode_ids: Vec<u32> = episodes.iter().map(|e| e.sonarr_episode_id).collect();
+    let processed = processed_subtitle_keys(conn, MediaType::TVShow, instance, episode_ids).await?;
+    let processed_ids: HashSet<u32> = processed.iter().map(|(id, _)| *id).collect();
+
+    let episodes = episodes
+        .into_iter()
+        .filter(|episode| {
+            has_unprocessed_subtitles(
+                episode.sonarr_episode_id,
+                &episode.subtitles,
+                &processed,
+                &processed_ids,
+            )
+        })
+        .collect();
+    Ok((episodes, processed))
+}
+
+/// Table and media ID column holding processed subtitles of the given media type
+fn processed_table(media_type: &MediaType) -> (&'static str, &'static str) {
+    match media_type {
+        MediaType::Movie => ("processed_movie_subtitles", "radarr_id"),
+        MediaType::TVShow => ("processed_episode_subtitles", "sonarr_episode_id"),
+    }
+}
+
+/// A processed subtitle record as stored in the database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessedRecord {
+    #[serde(default)]
+    pub instance: String,
+    pub media_type: MediaType,
+    pub media_id: u32,
+    pub title: String,
+    pub language_code: String,
+    pub language_name: String,
+    pub path: Option<String>,
+    pub action: Option<String>,
+    pub processed_at: i64,
+}
 
-        let mut stmt = conn.prepare(&query)?;
-        let params: Vec<&dyn rusqlite::ToSql> = radarr_ids
-            .iter()
-            .map(|id| id as &dyn rusqlite::ToSql)
-            .collect();
+/// Filter used to select processed subtitle records
+#[derive(Debug, Default, Clone)]
+pub struct RecordFilter {
+    pub instance: Option<String>,
+    pub media_type: Option<MediaType>,
+    pub ids: Vec<u32>,
+    pub title: Option<String>,
+    pub language: Option<String>,
+    pub action: Option<String>,
+}
 
-        let processed: HashSet<u32> =
-            stmt.query_map(params.as_slice(), |row| row.get(0))?
-                .collect::<std::result::Result<HashSet<u32>, rusqlite::Error>>()?;
+impl RecordFilter {
+    /// Returns true if the filter matches every record
+    pub fn is_empty(&self) -> bool {
+        self.instance.is_none()
+            && self.media_type.is_none()
+            && self.ids.is_empty()
+            && self.title.is_none()
+            && self.language.is_none()
+            && self.action.is_none()
+    }
 
-        Ok::<_, rusqlite::Error>(processed)
+    fn media_types(&self) -> Vec<MediaType> {
+        match &self.media_type {
+            Some(media_type) => vec![*media_type],
+            None => vec![MediaType::Movie, MediaType::TVShow],
+        }
+    }
+
+    /// WHERE clauses selecting the matching records, with their bound values.
+    /// IDs are split in chunks to stay within SQLite's limit on bound parameters,
+    /// with one clause per chunk.
+    fn where_clauses(&self, id_column: &str) -> Vec<(String, Vec<Value>)> {
+        if self.ids.is_empty() {
+            return vec![self.where_clause(id_column, &[])];
+        }
+        self.ids
+            .chunks(ID_CHUNK_SIZE)
+            .map(|chunk| self.where_clause(id_column, chunk))
+            .collect()
+    }
+
+    fn where_clause(&self, id_column: &str, ids: &[u32]) -> (String, Vec<Value>) {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(instance) = &self.instance {
+            conditions.push("instance = ?".to_string());
+            values.push(Value::Text(instance.clone()));
+        }
+        if !ids.is_empty() {
+            let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            conditions.push(format!("{id_column} IN ({placeholders})"));
+            values.extend(ids.iter().map(|id| Value::Integer(i64::from(*id))));
+        }
+        if let Some(title) = &self.title {
+            conditions.push("title LIKE ?".to_string());
+            values.push(Value::Text(format!("%{title}%")));
+        }
+        if let Some(language) = &self.language {
+            conditions.push("language_code = ?".to_string());
+            values.push(Value::Text(language.clone()));
+        }
+        if let Some(action) = &self.action {
+            conditions.push("action = ?".to_string());
+            values.push(Value::Text(action.clone()));
+        }
+
+        if conditions.is_empty() {
+            (String::new(), values)
+        } else {
+            (format!("WHERE {}", conditions.join(" AND ")), values)
+        }
+    }
+}
+
+/// Record counts grouped by media type, action and language
+#[derive(Debug, Default)]
+pub struct DbStats {
+    pub by_instance: Vec<(String, u64)>,
+    pub by_media_type: Vec<(MediaType, u64)>,
+    pub by_action: Vec<(String, u64)>,
+    pub by_language: Vec<(String, u64)>,
+}
+
+pub async fn stats(conn: Arc<Mutex<Connection>>) -> Result<DbStats> {
+    with_conn(conn, |conn| {
+        let mut stats = DbStats::default();
+        for media_type in [MediaType::Movie, MediaType::TVShow] {
+            let (table, _) = processed_table(&media_type);
+            let count: u64 =
+                conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })?;
+            stats.by_media_type.push((media_type, count));
+        }
+
+        let all_records = "SELECT instance, action, language_code, language_name FROM processed_movie_subtitles
+             UNION ALL
+             SELECT instance, action, language_code, language_name FROM processed_episode_subtitles";
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT CASE instance WHEN '' THEN 'unassigned' ELSE instance END, COUNT(*)
+             FROM ({all_records}) GROUP BY instance ORDER BY 2 DESC, 1"
+        ))?;
+        stats.by_instance = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_>>()?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT COALESCE(action, 'unknown'), COUNT(*) FROM ({all_records})
+             GROUP BY 1 ORDER BY 2 DESC, 1"
+        ))?;
+        stats.by_action = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_>>()?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT language_code || ' (' || MAX(language_name) || ')', COUNT(*) FROM ({all_records})
+             GROUP BY language_code ORDER BY 2 DESC, 1"
+        ))?;
+        stats.by_language = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_>>()?;
+
+        Ok(stats)
     })
     .await
-    .map_err(|e| rusqlite::Error::InvalidPath(e.to_string().into()))??;
+}
 
-    let mut unprocessed = Vec::new();
-    for movie in movies {
-        if !processed_ids.contains(&movie.radarr_id) {
-            unprocessed.push(movie);
-            continue;
+/// List records matching the filter, most recently processed first
+pub async fn list_records(
+    conn: Arc<Mutex<Connection>>,
+    filter: RecordFilter,
+    limit: Option<u32>,
+) -> Result<Vec<ProcessedRecord>> {
+    with_conn(conn, move |conn| {
+        let mut records = Vec::new();
+        for media_type in filter.media_types() {
+            let (table, id_column) = processed_table(&media_type);
+            for (where_clause, mut values) in filter.where_clauses(id_column) {
+                values.push(Value::Integer(limit.map_or(-1, i64::from)));
+                let mut stmt = conn.prepare_cached(&format!(
+                    "SELECT instance, {id_column}, title, language_code, language_name, path, action, processed_at
+                     FROM {table} {where_clause}
+                     ORDER BY processed_at DESC, id DESC LIMIT ?"
+                ))?;
+                let rows = stmt.query_map(params_from_iter(values), |row| {
+                    Ok(ProcessedRecord {
+                        instance: row.get(0)?,
+                        media_type,
+                        media_id: row.get(1)?,
+                        title: row.get(2)?,
+                        language_code: row.get(3)?,
+                        language_name: row.get(4)?,
+                        path: row.get(5)?,
+                        action: row.get(6)?,
+                        processed_at: row.get(7)?,
+                    })
+                })?;
+                for row in rows {
+                    records.push(row?);
+                }
+            }
         }
 
-        let mut has_unprocessed = false;
-        for sub in &movie.subtitles {
-            if let Some(ref code) = sub.audio_language_item.code2 {
-                if is_movie_subtitle_processed(conn.clone(), movie.radarr_id, code.clone()).await? {
-                    continue;
-                }
-                has_unprocessed = true;
-                break;
+        records.sort_by_key(|record| std::cmp::Reverse(record.processed_at));
+        if let Some(limit) = limit {
+            records.truncate(limit as usize);
+        }
+        Ok(records)
+    })
+    .await
+}
+
+/// Delete records matching the filter, returning the number of deleted rows
+pub async fn forget_records(conn: Arc<Mutex<Connection>>, filter: RecordFilter) -> Result<usize> {
+    with_conn(conn, move |conn| {
+        let tx = conn.transaction()?;
+        let mut deleted = 0;
+        for media_type in filter.media_types() {
+            let (table, id_column) = processed_table(&media_type);
+            for (where_clause, values) in filter.where_clauses(id_column) {
+                deleted += tx.execute(
+                    &format!("DELETE FROM {table} {where_clause}"),
+                    params_from_iter(values),
+                )?;
             }
         }
+        tx.commit()?;
+        Ok(deleted)
+    })
+    .await
+}
 
-        if has_unprocessed {
-            unprocessed.push(movie);
+/// Delete all processed subtitle records, returning the number of deleted rows
+pub async fn reset(conn: Arc<Mutex<Connection>>) -> Result<usize> {
+    forget_records(conn, RecordFilter::default()).await
+}
+
+/// Number of records created before instances were tracked, which belong to no instance
+pub async fn count_unassigned_records(conn: Arc<Mutex<Connection>>) -> Result<usize> {
+    with_conn(conn, |conn| {
+        let mut count = 0;
+        for media_type in [MediaType::Movie, MediaType::TVShow] {
+            let (table, _) = processed_table(&media_type);
+            count += conn.query_row(
+                &format!("SELECT COUNT(*) FROM {table} WHERE instance = ''"),
+                [],
+                |row| row.get::<_, usize>(0),
+            )?;
         }
-    }
+        Ok(count)
+    })
+    .await
+}
 
-    Ok(unprocessed)
+/// Assign records created before instances were tracked to the given instance,
+/// returning the number of assigned rows.
+/// Records the instance already has are left unassigned.
+pub async fn claim_unassigned_records(
+    conn: Arc<Mutex<Connection>>,
+    instance: String,
+) -> Result<usize> {
+    with_conn(conn, move |conn| {
+        let tx = conn.transaction()?;
+        let mut claimed = 0;
+        for media_type in [MediaType::Movie, MediaType::TVShow] {
+            let (table, _) = processed_table(&media_type);
+            claimed += tx.execute(
+                &format!("UPDATE OR IGNORE {table} SET instance = ?1 WHERE instance = ''"),
+                params![instance],
+            )?;
+        }
+        tx.commit()?;
+        Ok(claimed)
+    })
+    .await
 }
 
-pub async fn filter_unprocessed_episodes(
+/// Outcome of importing processed subtitle records
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+/// Merge records into the database on their unique keys.
+/// On conflict, the record with the newer `processed_at` is kept.
+pub async fn import_records(
     conn: Arc<Mutex<Connection>>,
-    episodes: Vec<Episode>,
-) -> Result<Vec<Episode>> {
-    if episodes.is_empty() {
-        println!("No episodes to filter");
-        return Ok(vec![]);
+    records: Vec<ProcessedRecord>,
+) -> Result<ImportSummary> {
+    with_conn(conn, move |conn| {
+        let tx = conn.transaction()?;
+        let mut summary = ImportSummary::default();
+        for record in records {
+            let (table, id_column) = processed_table(&record.media_type);
+            let existing: Option<i64> = tx
+                .query_row(
+                    &format!(
+                        "SELECT processed_at FROM {table}
+                         WHERE instance = ?1 AND {id_column} = ?2 AND language_code = ?3"
+                    ),
+                    params![record.instance, record.media_id, record.language_code],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            match existing {
+                None => {
+                    tx.execute(
+                        &format!(
+                            "INSERT INTO {table}
+                             (instance, {id_column}, title, language_code, language_name, path, action, processed_at)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+                        ),
+                        params![
+                            record.instance,
+                            record.media_id,
+                            record.title,
+                            record.language_code,
+                            record.language_name,
+                            record.path,
+                            record.action,
+                            record.processed_at
+                        ],
+                    )?;
+                    summary.inserted += 1;
+                }
+                Some(processed_at) if record.processed_at > processed_at => {
+                    tx.execute(
+                        &format!(
+                            "UPDATE {table}
+                             SET title = ?4, language_name = ?5, path = ?6, action = ?7, processed_at = ?8
+                             WHERE instance = ?1 AND {id_column} = ?2 AND language_code = ?3"
+                        ),
+                        params![
+                            record.instance,
+                            record.media_id,
+                            record.language_code,
+                            record.title,
+                            record.language_name,
+                            record.path,
+                            record.action,
+                            record.processed_at
+                        ],
+                    )?;
+                    summary.updated += 1;
+                }
+                Some(_) => summary.unchanged += 1,
+            }
+        }
+        tx.commit()?;
+        Ok(summary)
+    })
+    .await
+}
+
+/// Rebuild the database file, returning its size in bytes before and after
+pub async fn vacuum(conn: Arc<Mutex<Connection>>) -> Result<(u64, u64)> {
+    with_conn(conn, |conn| {
+        let size = |conn: &Connection| -> Result<u64> {
+            conn.query_row(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                [],
+                |row| row.get(0),
+            )
+        };
+        let before = size(conn)?;
+        conn.execute_batch("VACUUM")?;
+        let after = size(conn)?;
+        Ok((before, after))
+    })
+    .await
+}
+
+/// Outcome of an attempted action
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ActionResult {
+    /// Bazarr performed the action
+    Success,
+    /// Bazarr responded with an error status
+    Failed,
+    /// Bazarr could not be reached
+    ConnectionError,
+    /// The run was stopped before Bazarr responded
+    Interrupted,
+}
+
+impl fmt::Display for ActionResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActionResult::Success => write!(f, "success"),
+            ActionResult::Failed => write!(f, "failed"),
+            ActionResult::ConnectionError => write!(f, "connection-error"),
+            ActionResult::Interrupted => write!(f, "interrupted"),
+        }
     }
+}
 
-    let episode_ids: Vec<u32> = episodes.iter().map(|e| e.sonarr_episode_id).collect();
-    println!("Checking {} episodes in database", episode_ids.len());
-    let conn_clone = conn.clone();
-
-    let processed_ids: HashSet<u32> = tokio::task::spawn_blocking(move || {
-        let conn = conn_clone.blocking_lock();
-        let placeholders = episode_ids
-            .iter()
-            .map(|_| "?")
-            .collect::<Vec<_>>()
-            .join(",");
-        let query = format!(
-            "SELECT DISTINCT sonarr_episode_id FROM processed_episode_subtitles 
-             WHERE sonarr_episode_id IN ({})",
-            placeholders
-        );
+impl FromStr for ActionResult {
+    type Err = ();
 
-        let mut stmt = conn.prepare(&query)?;
-        let params: Vec<&dyn rusqlite::ToSql> = episode_ids
-            .iter()
-            .map(|id| id as &dyn rusqlite::ToSql)
-            .collect();
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "success" => Ok(ActionResult::Success),
+            "failed" => Ok(ActionResult::Failed),
+            "connection-error" => Ok(ActionResult::ConnectionError),
+            "interrupted" => Ok(ActionResult::Interrupted),
+            _ => Err(()),
+        }
+    }
+}
 
-        let processed: HashSet<u32> =
-            stmt.query_map(params.as_slice(), |row| row.get(0))?
-                .collect::<std::result::Result<HashSet<u32>, rusqlite::Error>>()?;
+/// A single attempted action as recorded in the action log
+#[derive(Debug, Clone)]
+pub struct ActionLogEntry {
+    pub run_id: String,
+    pub instance: String,
+    pub action: String,
+    pub options: Option<String>,
+    pub media_type: MediaType,
+    pub media_id: u32,
+    pub title: String,
+    pub language_code: Option<String>,
+    pub subtitle_path: Option<String>,
+    pub result: ActionResult,
+    pub http_status: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub performed_at: i64,
+}
 
-        Ok::<_, rusqlite::Error>(processed)
+pub async fn log_action(conn: Arc<Mutex<Connection>>, entry: ActionLogEntry) -> Result<()> {
+    with_conn(conn, move |conn| {
+        conn.execute(
+            "INSERT INTO action_log
+             (run_id, instance, action, options, media_type, media_id, title, language_code,
+              subtitle_path, result, http_status, error, duration_ms, performed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                entry.run_id,
+                entry.instance,
+                entry.action,
+                entry.options,
+                entry.media_type.to_string(),
+                entry.media_id,
+                entry.title,
+                entry.language_code,
+                entry.subtitle_path,
+                entry.result.to_string(),
+                entry.http_status,
+                entry.error,
+                entry.duration_ms,
+                entry.performed_at
+            ],
+        )?;
+        Ok(())
     })
     .await
-    .map_err(|e| rusqlite::Error::InvalidPath(e.to_string().into()))??;
+}
+
+/// Filter used to query the action log
+#[derive(Debug, Default, Clone)]
+pub struct HistoryFilter {
+    pub run_id: Option<String>,
+    pub instance: Option<String>,
+    pub media_type: Option<MediaType>,
+    pub title: Option<String>,
+    pub result: Option<ActionResult>,
+    /// Inclusive
+    pub since: Option<HistoryDate>,
+    /// Inclusive
+    pub until: Option<HistoryDate>,
+}
 
-    let mut unprocessed = Vec::new();
-    for episode in episodes {
-        if !processed_ids.contains(&episode.sonarr_episode_id) {
-            unprocessed.push(episode);
-            continue;
+/// A date or a date and time (`YYYY-MM-DD[ HH:MM:SS]`, UTC) bounding the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryDate {
+    /// Unix timestamp of the date and time, or of the start of the day
+    pub timestamp: i64,
+    /// Whether only a date was given, standing for the whole day
+    pub whole_day: bool,
+}
+
+impl FromStr for HistoryDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Invalid date '{s}', expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS");
+        let s = s.trim();
+        let (date, time) = match s.split_once([' ', 'T']) {
+            Some((date, time)) => (date, Some(time.trim())),
+            None => (s, None),
+        };
+        let numbers = |value: &str, separator| {
+            value
+                .split(separator)
+                .map(|part| part.parse::<i64>().ok())
+                .collect::<Option<Vec<_>>>()
+        };
+        let [year, month, day] = numbers(date, '-').ok_or_else(invalid)?[..] else {
+            return Err(invalid());
+        };
+        let clock = match time {
+            Some(time) => numbers(time, ':').ok_or_else(invalid)?,
+            None => vec![0, 0, 0],
+        };
+        let [hour, minute, second] = clock[..] else {
+            return Err(invalid());
+        };
+        if !(1..=12).contains(&month)
+            || !(0..24).contains(&hour)
+            || !(0..60).contains(&minute)
+            || !(0..60).contains(&second)
+        {
+            return Err(invalid());
+        }
+        let day_start = days_from_civil(year, month, day) * 86_400;
+        // days past the end of the month roll over to the next one
+        if !format_timestamp(day_start).starts_with(&format!("{year:04}-{month:02}-{day:02}")) {
+            return Err(invalid());
         }
+        Ok(HistoryDate {
+            timestamp: day_start + hour * 3_600 + minute * 60 + second,
+            whole_day: time.is_none(),
+        })
+    }
+}
 
-        let mut has_unprocessed = false;
-        for sub in &episode.subtitles {
-            if let Some(ref code) = sub.audio_language_item.code2 {
-                if is_episode_subtitle_processed(
-                    conn.clone(),
-                    episode.sonarr_episode_id,
-                    code.clone(),
-                )
-                .await?
-                {
-                    continue;
-                }
-                has_unprocessed = true;
-                break;
+/// Parse a text column, failing on values this version of `bb` does not know
+fn parse_column<T: FromStr>(row: &Row, idx: usize) -> Result<T> {
+    let value: String = row.get(idx)?;
+    value.parse().map_err(|_| {
+        rusqlite::Error::FromSqlConversionFailure(
+            idx,
+            Type::Text,
+            format!("unknown value '{value}'").into(),
+        )
+    })
+}
+
+/// Query the action log, most recent first
+pub async fn query_history(
+    conn: Arc<Mutex<Connection>>,
+    filter: HistoryFilter,
+    limit: Option<u32>,
+) -> Result<Vec<ActionLogEntry>> {
+    with_conn(conn, move |conn| {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(run_id) = filter.run_id {
+            conditions.push("run_id = ?");
+            values.push(Value::Text(run_id));
+        }
+        if let Some(instance) = filter.instance {
+            conditions.push("instance = ?");
+            values.push(Value::Text(instance));
+        }
+        if let Some(media_type) = filter.media_type {
+            conditions.push("media_type = ?");
+            values.push(Value::Text(media_type.to_string()));
+        }
+        if let Some(title) = filter.title {
+            conditions.push("title LIKE ?");
+            values.push(Value::Text(format!("%{title}%")));
+        }
+        if let Some(result) = filter.result {
+            conditions.push("result = ?");
+            values.push(Value::Text(result.to_string()));
+        }
+        if let Some(since) = filter.since {
+            conditions.push("performed_at >= ?");
+            values.push(Value::Integer(since.timestamp));
+        }
+        if let Some(until) = filter.until {
+            if until.whole_day {
+                conditions.push("performed_at < ?");
+                values.push(Value::Integer(until.timestamp + 86_400));
+            } else {
+                conditions.push("performed_at <= ?");
+                values.push(Value::Integer(until.timestamp));
             }
         }
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        values.push(Value::Integer(limit.map_or(-1, i64::from)));
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT run_id, instance, action, options, media_type, media_id, title, language_code,
+                    subtitle_path, result, http_status, error, duration_ms, performed_at
+             FROM action_log {where_clause}
+             ORDER BY performed_at DESC, id DESC LIMIT ?"
+        ))?;
+        let entries = stmt
+            .query_map(params_from_iter(values), |row| {
+                Ok(ActionLogEntry {
+                    run_id: row.get(0)?,
+                    instance: row.get(1)?,
+                    action: row.get(2)?,
+                    options: row.get(3)?,
+                    media_type: parse_column(row, 4)?,
+                    media_id: row.get(5)?,
+                    title: row.get(6)?,
+                    language_code: row.get(7)?,
+                    subtitle_path: row.get(8)?,
+                    result: parse_column(row, 9)?,
+                    http_status: row.get(10)?,
+                    error: row.get(11)?,
+                    duration_ms: row.get(12)?,
+                    performed_at: row.get(13)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(entries)
+    })
+    .await
+}
+
+/// Status of a run tracked in the checkpoints table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    Running,
+    Interrupted,
+    Completed,
+}
+
+impl fmt::Display for RunStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunStatus::Running => write!(f, "running"),
+            RunStatus::Interrupted => write!(f, "interrupted"),
+            RunStatus::Completed => write!(f, "completed"),
+        }
+    }
+}
+
+/// Parameters and progress of a run.
+/// The position points at the last completed subtitle.
+#[derive(Debug, Clone)]
+pub struct RunCheckpoint {
+    pub run_id: String,
+    pub instance: String,
+    pub media_type: MediaType,
+    /// JSON encoded parameters of the run
+    pub parameters: String,
+    pub series_id: Option<u32>,
+    pub media_id: Option<u32>,
+    pub language_code: Option<String>,
+    pub started_at: i64,
+}
+
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+pub async fn start_run(conn: Arc<Mutex<Connection>>, checkpoint: RunCheckpoint) -> Result<()> {
+    with_conn(conn, move |conn| {
+        conn.execute(
+            "INSERT INTO run_checkpoints
+             (run_id, instance, media_type, parameters, status, series_id, media_id, language_code,
+              started_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9)
+             ON CONFLICT(run_id) DO UPDATE SET status = excluded.status, updated_at = excluded.updated_at",
+            params![
+                checkpoint.run_id,
+                checkpoint.instance,
+                checkpoint.media_type.to_string(),
+                checkpoint.parameters,
+                RunStatus::Running.to_string(),
+                checkpoint.series_id,
+                checkpoint.media_id,
+                checkpoint.language_code,
+                checkpoint.started_at
+            ],
+        )?;
+        Ok(())
+    })
+    .await
+}
+
+/// Record the last completed subtitle of a run
+pub async fn save_checkpoint(
+    conn: Arc<Mutex<Connection>>,
+    run_id: String,
+    series_id: Option<u32>,
+    media_id: u32,
+    language_code: Option<String>,
+) -> Result<()> {
+    with_conn(conn, move |conn| {
+        conn.execute(
+            "UPDATE run_checkpoints
+             SET series_id = ?2, media_id = ?3, language_code = ?4, updated_at = ?5
+             WHERE run_id = ?1",
+            params![run_id, series_id, media_id, language_code, now()],
+        )?;
+        Ok(())
+    })
+    .await
+}
+
+pub async fn finish_run(
+    conn: Arc<Mutex<Connection>>,
+    run_id: String,
+    status: RunStatus,
+) -> Result<()> {
+    with_conn(conn, move |conn| {
+        conn.execute(
+            "UPDATE run_checkpoints SET status = ?2, updated_at = ?3 WHERE run_id = ?1",
+            params![run_id, status.to_string(), now()],
+        )?;
+        Ok(())
+    })
+    .await
+}
+
+/// Most recently started run of the instance and media type that did not complete
+pub async fn last_unfinished_run(
+    conn: Arc<Mutex<Connection>>,
+    instance: String,
+    media_type: MediaType,
+) -> Result<Option<RunCheckpoint>> {
+    with_conn(conn, move |conn| {
+        conn.query_row(
+            "SELECT run_id, parameters, series_id, media_id, language_code, started_at
+             FROM run_checkpoints
+             WHERE instance = ?1 AND media_type = ?2 AND status != ?3
+             ORDER BY started_at DESC, rowid DESC LIMIT 1",
+            params![
+                instance,
+                media_type.to_string(),
+                RunStatus::Completed.to_string()
+            ],
+            |row| {
+                Ok(RunCheckpoint {
+                    run_id: row.get(0)?,
+                    instance: instance.clone(),
+                    media_type,
+                    parameters: row.get(1)?,
+                    series_id: row.get(2)?,
+                    media_id: row.get(3)?,
+                    language_code: row.get(4)?,
+                    started_at: row.get(5)?,
+                })
+            },
+        )
+        .optional()
+    })
+    .await
+}
+
+/// Days since the unix epoch of a civil date (Howard Hinnant's algorithm)
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Format a unix timestamp as `YYYY-MM-DD HH:MM:SS` (UTC)
+pub fn format_timestamp(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86_400);
+    let secs = timestamp.rem_euclid(86_400);
 
-        if has_unprocessed {
-            unprocessed.push(episode);
+    // civil date from days since the unix epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Database with the tables created before the migrations, holding one legacy movie record
+    fn legacy_db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_tables(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO processed_movie_subtitles
+             (radarr_id, title, language_code, language_name, path, processed_at)
+             VALUES (1, 'Movie', 'en', 'English', '/movie.en.srt', 0)",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn index_exists(conn: &Connection, name: &str) -> bool {
+        conn.query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?1",
+            [name],
+            |_| Ok(()),
+        )
+        .optional()
+        .unwrap()
+        .is_some()
+    }
+
+    #[test]
+    fn migrations_keep_records_and_indexes() {
+        let mut conn = legacy_db();
+        migrate(&mut conn).unwrap();
+
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        assert!(index_exists(&conn, "idx_movie_radarr"));
+        assert!(index_exists(&conn, "idx_episode_sonarr"));
+
+        let (instance, title): (String, String) = conn
+            .query_row(
+                "SELECT instance, title FROM processed_movie_subtitles WHERE radarr_id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(instance, "");
+        assert_eq!(title, "Movie");
+    }
+
+    #[test]
+    fn migrations_are_applied_once() {
+        let mut conn = legacy_db();
+        migrate(&mut conn).unwrap();
+        create_tables(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(784_111_777), "1994-11-06 08:49:37");
+        // leap days, and the end of a leap year
+        assert_eq!(format_timestamp(951_825_600), "2000-02-29 12:00:00");
+        assert_eq!(format_timestamp(1_735_689_599), "2024-12-31 23:59:59");
+        assert_eq!(format_timestamp(4_107_542_400), "2100-03-01 00:00:00");
+        assert_eq!(format_timestamp(-1), "1969-12-31 23:59:59");
+    }
+
+    #[test]
+    fn parses_history_dates() {
+        let date = |s: &str| s.parse::<HistoryDate>();
+        assert_eq!(
+            date("1994-11-06"),
+            Ok(HistoryDate {
+                timestamp: 784_080_000,
+                whole_day: true
+            })
+        );
+        assert_eq!(
+            date(" 1994-11-06 08:49:37 "),
+            Ok(HistoryDate {
+                timestamp: 784_111_777,
+                whole_day: false
+            })
+        );
+        assert_eq!(
+            date("2024-02-29T00:00:00").map(|d| d.timestamp),
+            Ok(1_709_164_800)
+        );
+        for invalid in [
+            "",
+            "yesterday",
+            "2026-13-45",
+            "2025-02-29",
+            "2026-04-31",
+            "2026-01-01 24:00:00",
+            "2026-01-01 12:00",
+            "2026-01-01 12:-1:00",
+            "2026-01",
+        ] {
+            assert!(date(invalid).is_err(), "{invalid}");
         }
     }
 
-    Ok(unprocessed)
+    #[tokio::test]
+    async fn refuses_unknown_values_in_the_history() {
+        let mut conn = legacy_db();
+        migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO action_log
+             (run_id, instance, action, media_type, media_id, title, result, duration_ms, performed_at)
+             VALUES ('run', '', 'sync', 'movie', 1, 'Movie', 'exploded', 0, 0)",
+            [],
+        )
+        .unwrap();
+        let conn = Arc::new(Mutex::new(conn));
+
+        let result = query_history(conn, HistoryFilter::default(), None).await;
+        assert!(matches!(
+            result,
+            Err(rusqlite::Error::FromSqlConversionFailure(9, Type::Text, _))
+        ));
+    }
+
+    #[test]
+    fn media_type_alone_is_a_filter() {
+        let filter = RecordFilter {
+            media_type: Some(MediaType::Movie),
+            ..Default::default()
+        };
+        assert!(!filter.is_empty());
+        assert!(RecordFilter::default().is_empty());
+    }
+
+    #[tokio::test]
+    async fn filters_on_more_ids_than_sqlite_can_bind() {
+        let mut conn = legacy_db();
+        migrate(&mut conn).unwrap();
+        let conn = Arc::new(Mutex::new(conn));
+
+        // more than the 32766 parameters SQLite binds at most
+        let filter = RecordFilter {
+            ids: (1..=40_000).collect(),
+            ..Default::default()
+        };
+        let records = list_records(conn.clone(), filter.clone(), None).await;
+        assert_eq!(records.unwrap().len(), 1);
+        assert_eq!(
+            forget_records(conn.clone(), filter.clone()).await.unwrap(),
+            1
+        );
+        assert!(list_records(conn, filter, None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unassigned_records_are_only_assigned_explicitly() {
+        let mut conn = legacy_db();
+        migrate(&mut conn).unwrap();
+        let conn = Arc::new(Mutex::new(conn));
+
+        assert_eq!(count_unassigned_records(conn.clone()).await.unwrap(), 1);
+        let assigned = claim_unassigned_records(conn.clone(), "hd".to_string())
+            .await
+            .unwrap();
+        assert_eq!(assigned, 1);
+        assert_eq!(count_unassigned_records(conn.clone()).await.unwrap(), 0);
+
+        let filter = RecordFilter {
+            instance: Some("hd".to_string()),
+            ..Default::default()
+        };
+        let records = list_records(conn, filter, None).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].media_id, 1);
+    }
 }
@@ -0,0 +1,384 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use reqwest::{
+    header::{CONTENT_TYPE, WWW_AUTHENTICATE},
+    StatusCode,
+};
+use rusqlite::{ffi::ErrorCode, Connection, OpenFlags};
+use tokio::{
+    net::{lookup_host, TcpStream},
+    time::Instant,
+};
+
+use bazarr_bulk::{
+    connection::build_client,
+    data_types::{
+        app_config::{mask_credentials, AppConfig},
+        response::SystemStatusResponse,
+    },
+    db,
+    error::{error_chain, Error},
+};
+
+use crate::cli::GlobalOptions;
+
+/// Checks performed against each Bazarr instance, in order
+const INSTANCE_CHECKS: &[&str] = &[
+    "DNS",
+    "TCP connect",
+    "TLS handshake",
+    "Status call",
+    "Base URL",
+    "API key",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Outcome::Pass => "PASS",
+            Outcome::Fail => "FAIL",
+            Outcome::Skip => "SKIP",
+        })
+    }
+}
+
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    detail: String,
+}
+
+#[derive(Default)]
+struct Report {
+    checks: Vec<Check>,
+    /// Index of the first check of the instance being diagnosed
+    instance_start: usize,
+}
+
+impl Report {
+    fn add(&mut self, name: &'static str, outcome: Outcome, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            outcome,
+            detail: detail.into(),
+        });
+    }
+
+    fn pass(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.add(name, Outcome::Pass, detail);
+    }
+
+    fn fail(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.add(name, Outcome::Fail, detail);
+    }
+
+    fn skip(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.add(name, Outcome::Skip, detail);
+    }
+
+    /// Skip the instance checks that have not been performed yet
+    fn skip_remaining(&mut self, reason: &str) {
+        for name in INSTANCE_CHECKS {
+            if !self.checks[self.instance_start..]
+                .iter()
+                .any(|check| check.name == *name)
+            {
+                self.skip(name, reason);
+            }
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.outcome == Outcome::Fail)
+            .count()
+    }
+
+    fn print(&self) {
+        println!("\n{:<14} {:<6}  Details", "Check", "Result");
+        for (idx, check) in self.checks.iter().enumerate() {
+            // separate the checks of each instance and of the database
+            if idx > 0 && matches!(check.name, "Configuration" | "Database") {
+                println!();
+            }
+            println!("{:<14} {:<6}  {}", check.name, check.outcome, check.detail);
+        }
+    }
+}
+
+/// Run every check and print a pass/fail table
+pub async fn run(options: &GlobalOptions) -> Result<(), Error> {
+    let mut report = Report::default();
+    let configs = if options.all_instances {
+        AppConfig::all(options.config.as_deref())
+    } else {
+        AppConfig::new(options.config.as_deref(), options.instance.as_deref()).map(|c| vec![c])
+    };
+    match configs {
+        Ok(configs) => {
+            for mut config in configs {
+                options.apply_overrides(&mut config);
+                check_instance(&config, &mut report).await;
+            }
+        }
+        Err(e) => report.fail("Configuration", e.to_string()),
+    }
+    check_database(options.db_path.clone(), &mut report);
+
+    report.print();
+    match report.failures() {
+        0 => {
+            println!("\nAll checks passed");
+            Ok(())
+        }
+        failures => Err(Error::ChecksFailed(failures)),
+    }
+}
+
+/// Follow a request to the status endpoint of the instance step by step
+async fn check_instance(config: &AppConfig, report: &mut Report) {
+    report.instance_start = report.checks.len();
+    let setup = config
+        .construct_url()
+        .and_then(|url| Ok((config.instance_id()?, url, build_client(config)?)));
+    let (instance, url, client) = match setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            let detail = match config.instance_id() {
+                Ok(instance) => format!("instance {instance}: {e}"),
+                Err(_) => e.to_string(),
+            };
+            report.fail("Configuration", detail);
+            report.skip_remaining("invalid configuration");
+            return;
+        }
+    };
+    report.pass(
+        "Configuration",
+        format!("instance {instance} at {}", mask_credentials(&url)),
+    );
+
+    let timeout = (config.connect_timeout > 0).then(|| Duration::from_secs(config.connect_timeout));
+    if let Some(proxy) = &config.proxy {
+        report.skip("DNS", format!("resolved by the proxy {proxy}"));
+        report.skip(
+            "TCP connect",
+            format!("connections go through the proxy {proxy}"),
+        );
+    } else {
+        let host = url.host_str().unwrap_or_default().trim_matches(['[', ']']);
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<_> = match within(timeout, lookup_host((host, port))).await {
+            Ok(addrs) => addrs.collect(),
+            Err(e) => {
+                report.fail("DNS", format!("{host}: {e}"));
+                report.skip_remaining("host not resolved");
+                return;
+            }
+        };
+        let ips: Vec<_> = addrs.iter().map(|addr| addr.ip().to_string()).collect();
+        report.pass("DNS", format!("{host} resolves to {}", ips.join(", ")));
+
+        let mut error = String::from("no address to connect to");
+        let mut connected = None;
+        for addr in &addrs {
+            match within(timeout, TcpStream::connect(addr)).await {
+                Ok(_) => {
+                    connected = Some(addr);
+                    break;
+                }
+                Err(e) => error = format!("{addr}: {e}"),
+            }
+        }
+        match connected {
+            Some(addr) => report.pass("TCP connect", format!("connected to {addr}")),
+            None => {
+                report.fail("TCP connect", error);
+                report.skip_remaining("no connection");
+                return;
+            }
+        }
+    }
+
+    let https = url.scheme() == "https";
+    let mut status_url = url.clone();
+    status_url
+        .path_segments_mut()
+        .unwrap()
+        .push("system")
+        .push("status");
+    let started = Instant::now();
+    let response = match client.get(status_url).send().await {
+        Ok(response) => response,
+        Err(e) if https && e.is_connect() => {
+            report.fail("TLS handshake", error_chain(&e));
+            report.skip_remaining("no TLS connection");
+            return;
+        }
+        Err(e) => {
+            if https {
+                report.pass("TLS handshake", "connection established");
+            } else {
+                report.skip("TLS handshake", "plain HTTP");
+            }
+            report.fail("Status call", error_chain(&e));
+            report.skip_remaining("no response");
+            return;
+        }
+    };
+    let elapsed = started.elapsed();
+    if https {
+        report.pass("TLS handshake", "certificate accepted");
+    } else {
+        report.skip("TLS handshake", "plain HTTP");
+    }
+
+    let status = response.status();
+    let proxy_auth = response.headers().contains_key(WWW_AUTHENTICATE);
+    let html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("html"));
+    let body = response.text().await.unwrap_or_default();
+    // Bazarr serves its web interface for paths outside of the API
+    let html = html || body.trim_start().starts_with('<');
+    let parsed = serde_json::from_str::<SystemStatusResponse>(&body);
+
+    match (&parsed, status.is_success() && !html) {
+        (Ok(parsed), true) => report.pass(
+            "Status call",
+            format!(
+                "{status} in {} ms, Bazarr {}",
+                elapsed.as_millis(),
+                parsed
+                    .data
+                    .bazarr_version
+                    .as_deref()
+                    .unwrap_or("version unknown")
+            ),
+        ),
+        _ => report.fail("Status call", format!("{status}: {}", excerpt(&body))),
+    }
+
+    if html {
+        report.fail(
+            "Base URL",
+            format!(
+                "{} returned a web page instead of JSON, check baseUrl",
+                url.path()
+            ),
+        );
+    } else if status == StatusCode::NOT_FOUND {
+        report.fail(
+            "Base URL",
+            format!("{} was not found, check baseUrl", url.path()),
+        );
+    } else if status.is_success() {
+        match &parsed {
+            Ok(_) => report.pass("Base URL", format!("{} is the Bazarr API", url.path())),
+            Err(e) => report.fail(
+                "Base URL",
+                format!("unexpected response from {}: {e}", url.path()),
+            ),
+        }
+    } else if status == StatusCode::UNAUTHORIZED && !proxy_auth {
+        report.pass("Base URL", format!("{} is the Bazarr API", url.path()));
+    } else {
+        report.skip("Base URL", "the status call failed");
+    }
+
+    if status == StatusCode::UNAUTHORIZED && proxy_auth {
+        report.fail(
+            "API key",
+            "a reverse proxy asks for credentials, check username and password",
+        );
+    } else if status == StatusCode::UNAUTHORIZED {
+        report.fail("API key", "rejected by Bazarr, check apiKey");
+    } else if status.is_success() && parsed.is_ok() {
+        report.pass("API key", "accepted");
+    } else {
+        report.skip("API key", "the status call failed");
+    }
+}
+
+/// Verify that the database is intact and writable, without creating it and without
+/// locking it, as a running `bb` may hold the write lock
+fn check_database(custom_path: Option<PathBuf>, report: &mut Report) {
+    let path = match db::resolve_db_path(custom_path) {
+        Ok(path) => path,
+        Err(e) => return report.fail("Database", e),
+    };
+    if !path.exists() {
+        return report.skip(
+            "Database",
+            format!("{} does not exist yet, it will be created", path.display()),
+        );
+    }
+    match inspect_database(&path) {
+        Ok(detail) => report.pass("Database", format!("{} {detail}", path.display())),
+        Err(e) => report.fail("Database", format!("{}: {e}", path.display())),
+    }
+}
+
+/// Check the integrity of an existing database and that this process can write to it
+fn inspect_database(path: &Path) -> Result<&'static str, String> {
+    // opening the file for writing checks the actual access rights, e.g. of root
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("not writable: {e}"))?;
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .map_err(|e| e.to_string())?;
+    conn.busy_timeout(Duration::from_secs(1))
+        .map_err(|e| e.to_string())?;
+    match conn.query_row("PRAGMA quick_check", [], |row| row.get::<_, String>(0)) {
+        Ok(result) if result == "ok" => Ok("is intact and writable"),
+        Ok(result) => Err(result),
+        Err(e) if e.sqlite_error_code() == Some(ErrorCode::DatabaseBusy) => {
+            Ok("is writable and in use by another process")
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Await the future, giving up after the timeout if there is one
+async fn within<T, E: fmt::Display>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, String> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| format!("timed out after {}s", timeout.as_secs()))?
+            .map_err(|e| e.to_string()),
+        None => future.await.map_err(|e| e.to_string()),
+    }
+}
+
+/// Beginning of a response body, on a single line
+fn excerpt(body: &str) -> String {
+    let line = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    match line.char_indices().nth(80) {
+        Some((idx, _)) => format!("{}...", &line[..idx]),
+        None if line.is_empty() => "empty response".to_string(),
+        None => line,
+    }
+}
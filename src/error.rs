@@ -0,0 +1,201 @@
+use std::{fmt, io, path::PathBuf};
+
+use config::ConfigError;
+use reqwest::StatusCode;
+
+use crate::data_types::request::MediaType;
+
+/// Errors returned while talking to Bazarr or managing the database
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration or the command line options are invalid
+    Config(String),
+    /// Bazarr could not be reached, or the connection was lost
+    Connection(reqwest_middleware::Error),
+    /// Bazarr rejected the API key, or a reverse proxy asked for credentials
+    Auth { reverse_proxy: bool },
+    /// Bazarr responded with an unexpected HTTP status
+    Status { status: StatusCode, body: String },
+    /// A file or the command line could not be decoded
+    Decode { context: String, message: String },
+    /// A request to Bazarr or a database record could not be encoded
+    Encode { context: String, message: String },
+    /// A response of Bazarr could not be decoded
+    InvalidResponse {
+        endpoint: String,
+        /// Path of the field that failed to decode, e.g. `data[3].subtitles[0].forced`
+        field: Option<String>,
+        message: String,
+        /// File holding the raw response body, if it was saved
+        saved_to: Option<PathBuf>,
+        /// Why the raw response body could not be saved
+        save_error: Option<String>,
+    },
+    /// The database could not be read or written
+    Database(rusqlite::Error),
+    /// A file could not be read or written
+    Io(io::Error),
+    /// The Bazarr server does not support the requested action
+    Unsupported(String),
+    /// `bb doctor` found problems
+    ChecksFailed(usize),
+    /// The run was stopped before it completed, either gracefully or aborted
+    /// without waiting for the current subtitle
+    Interrupted,
+    /// A run was to be resumed, but no run of the media type was interrupted
+    NothingToResume(MediaType),
+    /// The run failed on some of the Bazarr instances, with the error of each
+    InstancesFailed {
+        failed: Vec<(String, Error)>,
+        total: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(message) | Error::Unsupported(message) => f.write_str(message),
+            Error::Connection(e) if self.is_response_timeout() => write!(
+                f,
+                "Bazarr did not respond within the request timeout: {}",
+                error_chain(e)
+            ),
+            Error::Connection(e) => write!(
+                f,
+                "Unable to connect to Bazarr: {}. Verify the url (or protocol, host and port) \
+                 of the configuration, or run `bb doctor` for details",
+                error_chain(e)
+            ),
+            Error::Auth {
+                reverse_proxy: true,
+            } => f.write_str(
+                "Unauthorized request! Please verify the username and password of the reverse proxy in front of Bazarr",
+            ),
+            Error::Auth {
+                reverse_proxy: false,
+            } => f.write_str(
+                "Unauthorized request! Please verify that the correct Bazarr API key has been set in the configuration file",
+            ),
+            Error::Status { status, body } if body.is_empty() => {
+                write!(f, "Bazarr responded with {status}")
+            }
+            Error::Status { status, body } => write!(f, "Bazarr responded with {status}: {body}"),
+            Error::Decode { context, message } => {
+                write!(f, "Failed to decode {context}: {message}")
+            }
+            Error::Encode { context, message } => {
+                write!(f, "Failed to encode {context}: {message}")
+            }
+            Error::InvalidResponse {
+                endpoint,
+                field,
+                message,
+                saved_to,
+                save_error,
+            } => {
+                write!(f, "Failed to decode the response of {endpoint}")?;
+                if let Some(field) = field {
+                    write!(f, " at `{field}`")?;
+                }
+                write!(f, ": {message}. ")?;
+                match (saved_to, save_error) {
+                    (Some(path), _) => {
+                        write!(f, "The raw response was saved to {}", path.display())
+                    }
+                    (None, Some(e)) => write!(f, "Failed to save the raw response: {e}"),
+                    (None, None) => f.write_str("Run with --debug to save the raw response"),
+                }
+            }
+            Error::Database(e) => write!(f, "Database error: {e}"),
+            Error::Io(e) => e.fmt(f),
+            Error::ChecksFailed(count) => write!(f, "{count} check(s) failed"),
+            Error::Interrupted => f.write_str("Run interrupted, continue it with --resume"),
+            Error::NothingToResume(media_type) => {
+                write!(f, "No interrupted {media_type} run found to resume")
+            }
+            Error::InstancesFailed { failed, total } => {
+                let names: Vec<_> = failed.iter().map(|(name, _)| name.as_str()).collect();
+                write!(
+                    f,
+                    "{} of {total} instance(s) failed: {}",
+                    failed.len(),
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl Error {
+    /// HTTP status of Bazarr's response, if it responded
+    pub fn http_status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Auth { .. } => Some(StatusCode::UNAUTHORIZED),
+            Error::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether Bazarr was reached but did not respond within the request timeout,
+    /// e.g. while still working on a long action
+    pub fn is_response_timeout(&self) -> bool {
+        match self {
+            Error::Connection(reqwest_middleware::Error::Reqwest(e)) => {
+                e.is_timeout() && !e.is_connect()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connection(e) => Some(e),
+            Error::Database(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest_middleware::Error> for Error {
+    fn from(e: reqwest_middleware::Error) -> Self {
+        Error::Connection(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Connection(e.into())
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Database(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+/// The error followed by its causes, which hold the details of network errors
+pub fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message = format!("{message}: {cause}");
+        source = cause.source();
+    }
+    message
+}
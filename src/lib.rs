@@ -0,0 +1,46 @@
+//! Bulk operations on the subtitles of movies and TV shows through Bazarr's API.
+//!
+//! [`client::BazarrClient`] is a typed async client of the API: it lists movies, TV shows
+//! and episodes with their subtitles, and performs actions such as sync or OCR fixes on a
+//! subtitle. [`actions::Action`] runs an action on a whole library, remembering processed
+//! subtitles in the SQLite database managed by [`db`]. The library does not print anything,
+//! runs report their progress through [`progress::Reporter`]. The `bb` command line tool is
+//! built on top of this crate.
+//!
+//! ```no_run
+//! use bazarr_bulk::{
+//!     client::{BazarrClient, ListQuery},
+//!     data_types::{
+//!         app_config::AppConfig,
+//!         request::{ActionCommands, ActionPayload},
+//!     },
+//! };
+//!
+//! # async fn run() -> Result<(), bazarr_bulk::error::Error> {
+//! let config = AppConfig::new(Some("config.json".as_ref()), None)?;
+//! let client = BazarrClient::from_config(&config)?;
+//! for movie in client.movies(&ListQuery::default()).await? {
+//!     for subtitle in &movie.subtitles {
+//!         let Some(payload) = ActionPayload::new(movie.radarr_id, "movie", subtitle) else {
+//!             continue;
+//!         };
+//!         client.perform(&ActionCommands::OCRFixes, payload).await?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod actions;
+pub mod client;
+pub mod connection;
+pub mod data_types;
+pub mod db;
+pub mod error;
+pub mod progress;
+pub mod rate_limit;
+pub mod retry;
+pub mod shutdown;
+
+#[cfg(test)]
+mod test_server;
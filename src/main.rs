@@ -1,18 +1,44 @@
-mod actions;
 mod cli;
-mod connection;
-mod data_types;
-mod db;
+mod console;
+mod doctor;
+mod signals;
 
+use std::process::ExitCode;
+
+use bazarr_bulk::error::Error;
 use clap::Parser;
 use cli::Cli;
-use data_types::app_config::AppConfig;
+
+/// Exit status for each kind of error, so scripts can tell them apart
+fn exit_code(error: &Error) -> u8 {
+    match error {
+        Error::Config(_) => 2,
+        Error::Connection(_) => 3,
+        Error::Auth { .. } => 4,
+        Error::Status { .. }
+        | Error::Decode { .. }
+        | Error::Encode { .. }
+        | Error::InvalidResponse { .. }
+        | Error::Unsupported(_) => 5,
+        Error::Database(_) | Error::Io(_) => 6,
+        Error::ChecksFailed(_) => 1,
+        // 128 + SIGINT, as when the process is killed by Ctrl-C
+        Error::Interrupted => 130,
+        Error::NothingToResume(_) => 7,
+        // the status of the first failure, each one was printed when it happened
+        Error::InstancesFailed { failed, .. } => failed.first().map_or(1, |(_, e)| exit_code(e)),
+    }
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     human_panic::setup_panic!();
     let cli = Cli::parse();
-    let config = AppConfig::new(cli.config.to_str().unwrap())?;
-    cli.run(config).await?;
-    Ok(())
+    match cli.run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(exit_code(&e))
+        }
+    }
 }
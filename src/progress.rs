@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use crate::{
+    actions::RunStats,
+    data_types::{
+        request::{ActionCommands, MediaType},
+        response::Subtitle,
+    },
+    error::Error,
+    rate_limit::Rate,
+};
+
+/// Progress of a run. Items are the movies, or the TV shows whose episodes are processed.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// The action is about to run on the movies or TV shows of the library
+    Started {
+        media_type: MediaType,
+        action: &'a ActionCommands,
+    },
+    /// Number of items selected, as reported by Bazarr with the first page of the listing
+    Selected {
+        count: u64,
+    },
+    /// Number of items of a page, when Bazarr does not report the total
+    Listed {
+        count: u64,
+    },
+    /// Items passed over without processing, as they precede the item of a resumed run
+    Skipped {
+        count: u64,
+    },
+    /// Movies, or episodes of the current TV show, whose subtitles are all processed already
+    AlreadyProcessed {
+        count: u64,
+    },
+    /// The resumed run continues from the given item
+    Resuming {
+        title: &'a str,
+    },
+    /// The last completed item of the resumed run was not found, starting from the beginning
+    ResumeNotFound,
+    ItemStarted {
+        id: u32,
+        title: &'a str,
+    },
+    ItemFinished {
+        id: u32,
+        title: &'a str,
+    },
+    /// Number of episodes of the current TV show left to process
+    Episodes {
+        count: u64,
+    },
+    EpisodeStarted {
+        id: u32,
+        title: &'a str,
+    },
+    EpisodeFinished {
+        id: u32,
+        title: &'a str,
+    },
+    /// The subtitle was processed by an earlier run
+    SubtitleSkipped {
+        title: &'a str,
+        subtitle: &'a Subtitle,
+    },
+    /// The action is being performed on the subtitle of the given movie or episode
+    Performing {
+        title: &'a str,
+        subtitle: &'a Subtitle,
+    },
+    Performed {
+        title: &'a str,
+        subtitle: &'a Subtitle,
+        result: Result<(), &'a Error>,
+    },
+    /// Adaptive throttling changed the request rate
+    RateChanged {
+        rate: Rate,
+        latency: Option<Duration>,
+    },
+    /// No movies or TV shows matched the filters
+    NothingFound,
+    /// The run is over, `interrupted` if it stopped before processing every item
+    Finished {
+        stats: &'a RunStats,
+        interrupted: bool,
+    },
+}
+
+/// Receives the progress of runs, e.g. to show progress bars
+pub trait Reporter: Send + Sync {
+    fn report(&self, event: Event);
+}
+
+impl<F: Fn(Event) + Send + Sync> Reporter for F {
+    fn report(&self, event: Event) {
+        self(event)
+    }
+}
+
+/// Ignores the progress of runs
+pub struct Silent;
+
+impl Reporter for Silent {
+    fn report(&self, _event: Event) {}
+}
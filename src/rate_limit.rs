@@ -0,0 +1,329 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex as SyncMutex},
+    time::Duration,
+};
+
+use http::Extensions;
+use reqwest::{Method, Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use tokio::{sync::Mutex, time::Instant};
+
+/// Maximum number of requests per second, parsed from `N/s` or `N/m`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+}
+
+impl Rate {
+    /// Minimum time between the start of two requests
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.per_second)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (count, unit) = s.split_once('/').unwrap_or((s, "s"));
+        let seconds = match unit.trim() {
+            "s" | "sec" | "second" => 1.0,
+            "m" | "min" | "minute" => 60.0,
+            other => return Err(format!("Unknown rate unit '{other}', expected s or m")),
+        };
+        let count: f64 = count
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{s}' is not a rate like 5/s or 30/m"))?;
+        if !count.is_finite() || count <= 0.0 {
+            return Err("The rate must be greater than zero".to_string());
+        }
+        Ok(Rate {
+            per_second: count / seconds,
+        })
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.per_second >= 1.0 {
+            write!(f, "{:.1}/s", self.per_second)
+        } else {
+            write!(f, "{:.1}/m", self.per_second * 60.0)
+        }
+    }
+}
+
+#[derive(Default)]
+struct LimiterState {
+    /// Earliest time the next request may start
+    next_request: Option<Instant>,
+    /// Time the last action finished
+    last_action: Option<Instant>,
+    /// Time Bazarr took to respond to the last action, excluding the time spent waiting here
+    last_action_latency: Option<Duration>,
+}
+
+/// Spaces out requests to Bazarr so bulk runs don't starve its own tasks.
+/// Every request is limited to `rate`, and actions (PATCH) additionally wait
+/// `pause` after the previous action finished.
+pub struct RateLimitMiddleware {
+    rate: SyncMutex<Option<Rate>>,
+    pub pause: Duration,
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(rate: Option<Rate>, pause: Duration) -> Self {
+        Self {
+            rate: SyncMutex::new(rate),
+            pause,
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn rate(&self) -> Option<Rate> {
+        *self.rate.lock().unwrap()
+    }
+
+    /// Time Bazarr took to respond to the last action
+    pub async fn last_action_latency(&self) -> Option<Duration> {
+        self.state.lock().await.last_action_latency
+    }
+
+    /// Change the rate, taking effect from the next request
+    pub fn set_rate(&self, rate: Rate) {
+        *self.rate.lock().unwrap() = Some(rate);
+    }
+
+    /// Reserve the next slot and return the time the request may start
+    async fn reserve(&self, is_action: bool) -> Instant {
+        let mut state = self.state.lock().await;
+        let mut start = Instant::now();
+        if let Some(next_request) = state.next_request {
+            start = start.max(next_request);
+        }
+        if is_action {
+            if let Some(last_action) = state.last_action {
+                start = start.max(last_action + self.pause);
+            }
+        }
+        if let Some(rate) = self.rate() {
+            state.next_request = Some(start + rate.interval());
+        }
+        start
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RateLimitMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let is_action = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        tokio::time::sleep_until(self.reserve(is_action).await).await;
+        let started = Instant::now();
+        let result = next.run(req, extensions).await;
+        if is_action {
+            let mut state = self.state.lock().await;
+            state.last_action = Some(Instant::now());
+            state.last_action_latency = Some(started.elapsed());
+        }
+        result
+    }
+}
+
+/// Smoothing factor of the moving averages of latency and errors
+const SMOOTHING: f64 = 0.3;
+
+#[derive(Default)]
+struct ThrottleState {
+    /// Moving average of the action latency, in seconds
+    latency: Option<f64>,
+    /// Moving average of the share of failed actions
+    error_rate: f64,
+}
+
+/// Adjusts the rate of the limiter to keep the latency of actions under a target.
+/// The rate is lowered below the achieved throughput when Bazarr slows down or fails,
+/// and raised again up to `max_rate` once it recovers.
+///
+/// Subtitles are processed one at a time, so the request rate is the only knob:
+/// there is no concurrency to lower.
+pub struct AdaptiveThrottle {
+    limiter: Arc<RateLimitMiddleware>,
+    pub target_latency: Duration,
+    pub min_rate: Rate,
+    pub max_rate: Rate,
+    state: SyncMutex<ThrottleState>,
+}
+
+impl AdaptiveThrottle {
+    /// Start at the maximum rate
+    pub fn new(
+        limiter: Arc<RateLimitMiddleware>,
+        target_latency: Duration,
+        max_rate: Rate,
+    ) -> Self {
+        limiter.set_rate(max_rate);
+        Self {
+            limiter,
+            target_latency,
+            min_rate: Rate {
+                per_second: (1.0 / 60.0_f64).min(max_rate.per_second),
+            },
+            max_rate,
+            state: SyncMutex::default(),
+        }
+    }
+
+    pub fn rate(&self) -> Rate {
+        self.limiter.rate().unwrap_or(self.max_rate)
+    }
+
+    /// Average latency of recent actions
+    pub fn latency(&self) -> Option<Duration> {
+        self.state
+            .lock()
+            .unwrap()
+            .latency
+            .map(Duration::from_secs_f64)
+    }
+
+    /// Account for the last finished action and return the new rate if it changed
+    pub async fn observe(&self, failed: bool) -> Option<Rate> {
+        let latency = self.limiter.last_action_latency().await?;
+        let mut state = self.state.lock().unwrap();
+        let latency = latency.as_secs_f64();
+        let average = match state.latency {
+            Some(average) => average + SMOOTHING * (latency - average),
+            None => latency,
+        };
+        state.latency = Some(average);
+        state.error_rate += SMOOTHING * (f64::from(u8::from(failed)) - state.error_rate);
+
+        let target = self.target_latency.as_secs_f64();
+        let current = self.rate().per_second;
+        let per_second = if average > target || state.error_rate > 0.2 {
+            // back off from the throughput Bazarr currently achieves
+            current.min(1.0 / average.max(f64::EPSILON)) * 0.7
+        } else if average < target * 0.8 && state.error_rate < 0.05 {
+            current * 1.2
+        } else {
+            current
+        };
+        let per_second = per_second.clamp(self.min_rate.per_second, self.max_rate.per_second);
+
+        if (per_second - current).abs() / current < 0.01 {
+            return None;
+        }
+        let rate = Rate { per_second };
+        self.limiter.set_rate(rate);
+        Some(rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(s: &str) -> Rate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(rate("5/s").per_second, 5.0);
+        assert_eq!(rate("5").per_second, 5.0);
+        assert_eq!(rate(" 30 / min ").per_second, 0.5);
+        assert_eq!(rate("0.5/second").per_second, 0.5);
+        assert_eq!(rate("120/m").interval(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        for s in ["", "fast", "5/h", "0/s", "-1/s", "inf/s", "NaN"] {
+            assert!(s.parse::<Rate>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn displays_rates() {
+        assert_eq!(rate("5/s").to_string(), "5.0/s");
+        assert_eq!(rate("30/m").to_string(), "30.0/m");
+    }
+
+    #[tokio::test]
+    async fn spaces_out_requests() {
+        let limiter = RateLimitMiddleware::new(Some(rate("2/s")), Duration::ZERO);
+        let first = limiter.reserve(false).await;
+        let second = limiter.reserve(false).await;
+        assert_eq!(second - first, Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn pauses_between_actions_only() {
+        let limiter = RateLimitMiddleware::new(None, Duration::from_secs(10));
+        let finished = Instant::now();
+        limiter.state.lock().await.last_action = Some(finished);
+        assert!(limiter.reserve(false).await < finished + Duration::from_secs(1));
+        assert_eq!(
+            limiter.reserve(true).await,
+            finished + Duration::from_secs(10)
+        );
+    }
+
+    fn throttle() -> AdaptiveThrottle {
+        let limiter = Arc::new(RateLimitMiddleware::new(None, Duration::ZERO));
+        AdaptiveThrottle::new(limiter, Duration::from_secs(2), rate("10/s"))
+    }
+
+    /// Record an action that took `latency` seconds and let the throttle account for it
+    async fn observe(throttle: &AdaptiveThrottle, latency: f64, failed: bool) -> Option<Rate> {
+        throttle.limiter.state.lock().await.last_action_latency =
+            Some(Duration::from_secs_f64(latency));
+        throttle.observe(failed).await
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_finished_action() {
+        let throttle = throttle();
+        assert_eq!(throttle.rate(), rate("10/s"));
+        assert_eq!(throttle.observe(false).await, None);
+    }
+
+    #[tokio::test]
+    async fn backs_off_below_the_throughput_of_slow_actions() {
+        let throttle = throttle();
+        let slowed = observe(&throttle, 4.0, false).await.unwrap();
+        assert!((slowed.per_second - 0.25 * 0.7).abs() < 1e-9);
+        assert_eq!(throttle.rate(), slowed);
+        assert_eq!(throttle.latency(), Some(Duration::from_secs(4)));
+    }
+
+    #[tokio::test]
+    async fn stays_within_bounds() {
+        let throttle = throttle();
+        observe(&throttle, 1000.0, false).await;
+        assert_eq!(throttle.rate(), throttle.min_rate);
+
+        for _ in 0..100 {
+            observe(&throttle, 0.1, false).await;
+        }
+        assert_eq!(throttle.rate(), throttle.max_rate);
+        assert_eq!(observe(&throttle, 0.1, false).await, None);
+    }
+
+    #[tokio::test]
+    async fn backs_off_when_actions_fail() {
+        let throttle = throttle();
+        // fast, but the moving share of failures is over 20%
+        let slowed = observe(&throttle, 0.1, true).await.unwrap();
+        assert!((slowed.per_second - 7.0).abs() < 1e-9);
+    }
+}
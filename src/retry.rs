@@ -0,0 +1,291 @@
+use std::time::{Duration, SystemTime};
+
+use http::Extensions;
+use reqwest::{header::RETRY_AFTER, Method, Request, Response};
+use reqwest_middleware::{Error, Middleware, Next, Result};
+use reqwest_retry::{policies::ExponentialBackoff, Jitter, RetryDecision, RetryPolicy, Retryable};
+
+use crate::db::days_from_civil;
+
+/// Retries requests that failed with a transient error (lost connection, timeout, 5xx, 429)
+/// with exponential backoff and jitter, waiting as long as asked by a `Retry-After` header.
+///
+/// Read requests are idempotent and retried up to `read_retries` times. Actions (PATCH) run
+/// on Bazarr and may be duplicated when retried, so they are only retried `action_retries`
+/// times, except when the request never reached Bazarr (connection refused).
+pub struct RetryMiddleware {
+    pub min_retry_interval: Duration,
+    pub max_retry_interval: Duration,
+    pub read_retries: u32,
+    pub action_retries: u32,
+}
+
+impl RetryMiddleware {
+    fn backoff(&self, max_retries: u32) -> ExponentialBackoff {
+        ExponentialBackoff::builder()
+            .retry_bounds(self.min_retry_interval, self.max_retry_interval)
+            .jitter(Jitter::Bounded)
+            .build_with_max_retries(max_retries)
+    }
+}
+
+/// Delay requested by the `Retry-After` header
+fn retry_after(result: &Result<Response>) -> Option<Duration> {
+    let response = result.as_ref().ok()?;
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+/// Parse a `Retry-After` value, either a number of seconds or an HTTP date (RFC 9110).
+/// Dates in the past ask to retry immediately.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = parse_http_date(value)?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Parse an HTTP date in the preferred IMF-fixdate format (`Sun, 06 Nov 1994 08:49:37 GMT`),
+/// or in one of the obsolete RFC 850 (`Sunday, 06-Nov-94 08:49:37 GMT`)
+/// and asctime (`Sun Nov  6 08:49:37 1994`) formats
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let (day, month, year, time) = match parts[..] {
+        [_, day, month, year, time, "GMT"] => (day, month, year.parse().ok()?, time),
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            let year: i64 = year.parse().ok()?;
+            // two-digit years of RFC 850, 70 to 99 are from the last century
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            (day, month, year, time)
+        }
+        [_, month, day, time, year] => (day, month, year.parse().ok()?, time),
+        _ => return None,
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let mut time = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let timestamp =
+        days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second;
+    let timestamp = u64::try_from(timestamp).ok()?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(timestamp))
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Whether the request failed before reaching the server
+fn is_connect_error(result: &Result<Response>) -> bool {
+    matches!(result, Err(Error::Reqwest(e)) if e.is_connect())
+}
+
+#[async_trait::async_trait]
+impl Middleware for RetryMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let idempotent = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        let start_time = SystemTime::now();
+        let mut past_retries = 0;
+        loop {
+            // requests with a streaming body cannot be sent again
+            let Some(request) = req.try_clone() else {
+                return next.run(req, extensions).await;
+            };
+            let result = next.clone().run(request, extensions).await;
+
+            if Retryable::from_reqwest_response(&result) != Some(Retryable::Transient) {
+                return result;
+            }
+            let max_retries = if idempotent {
+                self.read_retries
+            } else if is_connect_error(&result) {
+                self.read_retries.max(self.action_retries)
+            } else {
+                self.action_retries
+            };
+            let RetryDecision::Retry { execute_after } = self
+                .backoff(max_retries)
+                .should_retry(start_time, past_retries)
+            else {
+                return result;
+            };
+
+            let delay = match retry_after(&result) {
+                // give up rather than wait longer than allowed
+                Some(delay) if delay > self.max_retry_interval => return result,
+                Some(delay) => delay,
+                None => execute_after
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default(),
+            };
+            tokio::time::sleep(delay).await;
+            past_retries += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    use super::*;
+    use crate::test_server::{Reply, TestServer};
+
+    /// Sun, 06 Nov 1994 08:49:37 GMT
+    const DATE: u64 = 784_111_777;
+
+    fn at(timestamp: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp)
+    }
+
+    #[test]
+    fn parses_seconds() {
+        let now = SystemTime::now();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("-1", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        for value in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(value), Some(at(DATE)), "{value}");
+        }
+        assert_eq!(
+            parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"),
+            Some(SystemTime::UNIX_EPOCH)
+        );
+        assert_eq!(
+            parse_http_date("Tue, 29 Feb 2028 23:59:59 GMT"),
+            Some(at(1_835_481_599))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for value in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+        ] {
+            assert_eq!(parse_http_date(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn waits_until_the_date() {
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(
+            parse_retry_after(date, at(DATE - 30)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_retry_after(date, at(DATE + 30)), Some(Duration::ZERO));
+    }
+
+    fn client(read_retries: u32, action_retries: u32) -> ClientWithMiddleware {
+        ClientBuilder::new(reqwest::Client::new())
+            .with(RetryMiddleware {
+                min_retry_interval: Duration::from_millis(1),
+                max_retry_interval: Duration::from_secs(2),
+                read_retries,
+                action_retries,
+            })
+            .build()
+    }
+
+    /// Server failing the first `failures` requests with the given reply
+    async fn failing(failures: usize, reply: fn() -> Reply) -> TestServer {
+        let count = AtomicUsize::new(0);
+        TestServer::start(move |_| {
+            if count.fetch_add(1, Ordering::SeqCst) < failures {
+                reply()
+            } else {
+                Reply::json("{}")
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn retries_reads_until_they_succeed() {
+        let server = failing(2, || Reply::status(503)).await;
+        let response = client(2, 0).get(server.url.clone()).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(server.received().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_retries() {
+        let server = failing(usize::MAX, || Reply::status(500)).await;
+        let response = client(2, 0).get(server.url.clone()).send().await.unwrap();
+        assert_eq!(response.status(), 500);
+        assert_eq!(server.received().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_errors() {
+        let server = failing(1, || Reply::status(404)).await;
+        let response = client(2, 0).get(server.url.clone()).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn spares_actions_that_reached_bazarr() {
+        let server = failing(1, || Reply::status(503)).await;
+        let response = client(2, 0).patch(server.url.clone()).send().await.unwrap();
+        assert_eq!(response.status(), 503);
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "PATCH");
+        assert_eq!(received[0].target, "/api");
+
+        let server = failing(1, || Reply::status(503)).await;
+        let response = client(2, 1).patch(server.url.clone()).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(server.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn waits_as_long_as_asked() {
+        let server = failing(1, || Reply::status(429).header("Retry-After", "1")).await;
+        let started = std::time::Instant::now();
+        let response = client(2, 0).get(server.url.clone()).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn gives_up_rather_than_wait_too_long() {
+        let server = failing(1, || Reply::status(429).header("Retry-After", "60")).await;
+        let response = client(2, 0).get(server.url.clone()).send().await.unwrap();
+        assert_eq!(response.status(), 429);
+        assert_eq!(server.received().len(), 1);
+    }
+}
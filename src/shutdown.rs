@@ -0,0 +1,78 @@
+use std::future::Future;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::error::Error;
+
+/// Shared flags to stop a run, gracefully or immediately.
+/// The `bb` binary sets them on Ctrl-C and SIGTERM, embedding programs call
+/// [`Shutdown::request`] and [`Shutdown::abort`] themselves.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    aborted: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl Shutdown {
+    /// Stop after the current subtitle, waiting at most the grace period for it
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Stop immediately, failing the run with [`Error::Interrupted`]
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.request();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once a shutdown has been requested and the grace period has elapsed,
+    /// or as soon as the run is aborted
+    pub async fn grace_period_elapsed(&self, grace_period: Duration) {
+        // a Notified future receives notifications from the moment it is created
+        let notified = self.notify.notified();
+        if !self.is_requested() {
+            notified.await;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(grace_period) => {}
+            _ = self.aborted() => {}
+        }
+    }
+
+    /// Resolves once the run is aborted
+    pub async fn aborted(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if self.is_aborted() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Run the future, giving up with [`Error::Interrupted`] when the run is aborted
+    pub async fn until_aborted<T>(
+        &self,
+        future: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        tokio::select! {
+            result = future => result,
+            _ = self.aborted() => Err(Error::Interrupted),
+        }
+    }
+}
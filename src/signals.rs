@@ -0,0 +1,42 @@
+use bazarr_bulk::shutdown::Shutdown;
+
+/// Listen for Ctrl-C and SIGTERM.
+/// The first signal requests a graceful shutdown, the second one aborts the run.
+pub fn listen() -> Shutdown {
+    let shutdown = Shutdown::default();
+    let handle = shutdown.clone();
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        eprintln!(
+            "\nStopping after the current subtitle, press Ctrl-C again to exit immediately..."
+        );
+        handle.request();
+
+        wait_for_signal().await;
+        eprintln!("\nExiting immediately");
+        handle.abort();
+    });
+    shutdown
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
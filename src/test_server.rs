@@ -0,0 +1,141 @@
+//! Minimal HTTP server standing in for Bazarr in tests
+
+use std::sync::{Arc, Mutex};
+
+use reqwest::{StatusCode, Url};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Request received by the server
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub method: String,
+    /// Path and query, e.g. `/api/movies?start=0&length=2`
+    pub target: String,
+}
+
+impl Received {
+    /// Values of the query parameter
+    pub fn query(&self, name: &str) -> Vec<String> {
+        let url = Url::parse(&format!("http://localhost{}", self.target)).unwrap();
+        url.query_pairs()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .collect()
+    }
+}
+
+/// Response sent by the server
+pub struct Reply {
+    status: StatusCode,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Reply {
+    pub fn json(body: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::OK,
+            headers: vec![("Content-Type", "application/json".to_string())],
+            body: body.into(),
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status: StatusCode::from_u16(status).unwrap(),
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&Received) -> Reply + Send + Sync;
+
+pub struct TestServer {
+    /// URL of the API, e.g. `http://127.0.0.1:1234/api`
+    pub url: Url,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl TestServer {
+    /// Answer every request with the reply of `handler`, until the test ends
+    pub async fn start(handler: impl Fn(&Received) -> Reply + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let log = received.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, handler.clone(), log.clone()));
+            }
+        });
+        Self {
+            url: url.parse().unwrap(),
+            received,
+        }
+    }
+
+    /// Requests received so far, in order
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// Targets of the requests received so far
+    pub fn targets(&self) -> Vec<String> {
+        self.received().into_iter().map(|r| r.target).collect()
+    }
+}
+
+/// Answer a single request, then close the connection
+async fn serve(stream: TcpStream, handler: Arc<Handler>, log: Arc<Mutex<Vec<Received>>>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    let mut parts = line.split_whitespace();
+    let request = Received {
+        method: parts.next().unwrap_or_default().to_string(),
+        target: parts.next().unwrap_or_default().to_string(),
+    };
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.unwrap();
+
+    let reply = handler(&request);
+    log.lock().unwrap().push(request);
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status.as_u16(),
+        reply.status.canonical_reason().unwrap_or_default(),
+        reply.body.len()
+    );
+    for (name, value) in &reply.headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    response.push_str(&reply.body);
+    let mut stream = reader.into_inner();
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}